@@ -1,20 +1,66 @@
-use anyhow::*;
+use anyhow::{bail, Result};
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::fs;
 
 // This script copies the `assets` folder to the app's `dist` folder
 // Not sure if this is necessary (definitely not for WASM...)
 fn main() -> Result<()> {
-    // This tells cargo to rerun this script if something in /res/ changes.
-    println!("cargo:rerun-if-changed=assets/*");
+    // This tells cargo to rerun this script if something in /assets/ changes.
+    // Cargo doesn't expand globs here, a folder is checked for changes to anything inside it.
+    println!("cargo:rerun-if-changed=assets");
+
+    // Before the copy, so a problem with the assets can't stop the shaders being checked
+    validate_shaders()?;
 
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("assets/models/");
+    // Everything (models, textures, scenes) sits directly in `assets`. This used to copy
+    // `assets/models/`, which doesn't exist, so the script failed and nothing would build.
+    let paths_to_copy = vec!["assets/"];
     copy_items(&paths_to_copy, out_dir, &copy_options)?;
 
     Ok(())
 }
+
+// Parses and validates every shader with naga, so mistakes show up as build errors
+// instead of a panic in `create_shader_module` at runtime
+fn validate_shaders() -> Result<()> {
+    // Directories get checked recursively for changes
+    println!("cargo:rerun-if-changed=src/shaders");
+    println!("cargo:rerun-if-changed=src/shader.wgsl");
+    println!("cargo:rerun-if-changed=src/light.wgsl");
+
+    let mut failed = Vec::new();
+    for entry in glob::glob("src/**/*.wgsl")? {
+        let path = entry?;
+        let path_name = path.display().to_string();
+        let source = fs::read_to_string(&path)?;
+
+        let module = match naga::front::wgsl::parse_str(&source) {
+            Ok(module) => module,
+            Err(e) => {
+                for line in e.emit_to_string(&source).lines() {
+                    println!("cargo:warning={}: {}", path_name, line);
+                }
+                failed.push(path_name);
+                continue;
+            }
+        };
+        let mut validator = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        );
+        if let Err(e) = validator.validate(&module) {
+            println!("cargo:warning={}: {:?}", path_name, e);
+            failed.push(path_name);
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("Invalid shaders: {}", failed.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,280 @@
+use std::f32::consts::PI;
+
+use cgmath::prelude::*;
+
+use crate::model;
+
+// Instances
+// Lets us duplicate objects in a scene with less cost
+pub struct Instance {
+    pub position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+}
+
+impl Instance {
+    // Tilts each instance 45 degrees around the direction from the origin,
+    // so neighbours don't all face the same way
+    fn tilted(position: cgmath::Vector3<f32>) -> Self {
+        let rotation = if position.is_zero() {
+            cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
+        } else {
+            cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+        };
+        Self { position, rotation }
+    }
+
+    // Undoes `Instance::to_raw`, e.g. after reading instances back from the GPU
+    pub fn from_raw(raw: &InstanceRaw) -> Self {
+        let model = cgmath::Matrix4::from(raw.model);
+        let position = model.w.truncate();
+        // Normalize the axes in case something scaled the matrix
+        let rotation = cgmath::Matrix3::from_cols(
+            model.x.truncate().normalize(),
+            model.y.truncate().normalize(),
+            model.z.truncate().normalize(),
+        );
+        Self {
+            position,
+            rotation: cgmath::Quaternion::from(rotation).normalize(),
+        }
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        let model =
+            cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation);
+        InstanceRaw {
+            model: model.into(),
+            normal: cgmath::Matrix3::from(self.rotation).into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+}
+
+impl InstanceRaw {
+    // For transforms that can scale, e.g. a scene node's. The normals need the inverse
+    // transpose then, a plain rotation like `Instance::to_raw` uses would skew them.
+    pub fn from_matrix(model: cgmath::Matrix4<f32>) -> Self {
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(linear);
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+}
+
+impl model::Vertex for InstanceRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
+                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
+                // for each vec4. We don't have to do this in code though.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+// Common ways of laying out a bunch of instances, centered on the origin
+#[derive(Copy, Clone, Debug)]
+pub enum SpawnPattern {
+    // Flat grid on the XZ plane
+    Grid {
+        rows: u32,
+        cols: u32,
+        spacing: f32,
+    },
+    // Evenly spaced around a ring at `height`
+    Circle {
+        count: u32,
+        radius: f32,
+        height: f32,
+    },
+    // Evenly spread over the surface of a sphere (Fibonacci lattice)
+    Sphere {
+        count: u32,
+        radius: f32,
+    },
+    // Scattered inside a cube from `-extent` to `extent`. The same seed gives the same layout.
+    Random {
+        count: u32,
+        extent: f32,
+        seed: u64,
+    },
+}
+
+impl SpawnPattern {
+    pub fn generate(&self) -> Vec<Instance> {
+        match *self {
+            SpawnPattern::Grid {
+                rows,
+                cols,
+                spacing,
+            } => (0..rows)
+                .flat_map(|z| {
+                    (0..cols).map(move |x| {
+                        let x = spacing * (x as f32 - cols as f32 / 2.0);
+                        let z = spacing * (z as f32 - rows as f32 / 2.0);
+                        Instance::tilted(cgmath::Vector3 { x, y: 0.0, z })
+                    })
+                })
+                .collect(),
+            SpawnPattern::Circle {
+                count,
+                radius,
+                height,
+            } => (0..count)
+                .map(|i| {
+                    let angle = 2.0 * PI * i as f32 / count as f32;
+                    Instance::tilted(cgmath::Vector3 {
+                        x: radius * angle.cos(),
+                        y: height,
+                        z: radius * angle.sin(),
+                    })
+                })
+                .collect(),
+            SpawnPattern::Sphere { count, radius } => {
+                // Each point steps around by the golden angle while moving from the top to the bottom
+                let golden_angle = PI * (3.0 - 5.0_f32.sqrt());
+                (0..count)
+                    .map(|i| {
+                        let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                        let ring_radius = (1.0 - y * y).sqrt();
+                        let angle = golden_angle * i as f32;
+                        Instance::tilted(
+                            cgmath::Vector3 {
+                                x: ring_radius * angle.cos(),
+                                y,
+                                z: ring_radius * angle.sin(),
+                            } * radius,
+                        )
+                    })
+                    .collect()
+            }
+            SpawnPattern::Random {
+                count,
+                extent,
+                seed,
+            } => {
+                let mut rng = SplitMix64(seed);
+                (0..count)
+                    .map(|_| {
+                        let mut coordinate = || (rng.next_f32() * 2.0 - 1.0) * extent;
+                        let x = coordinate();
+                        let y = coordinate();
+                        let z = coordinate();
+                        Instance::tilted(cgmath::Vector3 { x, y, z })
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Tiny seeded random number generator, so random layouts are the same every run
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // 0.0 to 1.0
+    fn next_f32(&mut self) -> f32 {
+        // Top 24 bits fit exactly in an f32's mantissa
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Tint for one instance, kept in its own buffer so `InstanceRaw` (and every pipeline
+// reading it) stays the same. Multiplied with the diffuse texture color.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceColor {
+    pub color: [f32; 4],
+}
+
+impl InstanceColor {
+    // No tint
+    pub const WHITE: Self = Self { color: [1.0; 4] };
+}
+
+impl model::Vertex for InstanceColor {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceColor>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[wgpu::VertexAttribute {
+                offset: 0,
+                // Right after the `InstanceRaw` attributes
+                shader_location: 12,
+                format: wgpu::VertexFormat::Float32x4,
+            }],
+        }
+    }
+}
+
+// Temporarily replaces an instance's color, e.g. to flash it when it's hit or selected
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorOverride {
+    pub color: [f32; 4],
+    // Frames left before it expires, `None` lasts until it's removed
+    pub frames_left: Option<u32>,
+}
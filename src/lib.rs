@@ -1,4 +1,8 @@
+use std::collections::HashMap;
 use std::iter;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use cgmath::prelude::*;
 use wgpu::include_wgsl;
@@ -12,13 +16,90 @@ use winit::{
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+mod instance;
 mod model;
+mod pass;
+mod physics;
+mod pipeline;
+mod primitives;
+mod profiling;
 mod resources;
+mod screenshot;
+mod showcase;
 mod texture;
+mod viewport;
+use instance::{ColorOverride, Instance, InstanceColor, InstanceRaw};
 use model::{DrawLight, DrawModel, Vertex};
 
 // Constants for instances
 const NUM_INSTANCES_PER_ROW: u32 = 10;
+// Layouts to switch between with I, starting with the first
+const SPAWN_PATTERNS: [instance::SpawnPattern; 4] = [
+    instance::SpawnPattern::Grid {
+        rows: NUM_INSTANCES_PER_ROW,
+        cols: NUM_INSTANCES_PER_ROW,
+        spacing: 3.0,
+    },
+    instance::SpawnPattern::Circle {
+        count: NUM_INSTANCES_PER_ROW * NUM_INSTANCES_PER_ROW,
+        radius: 15.0,
+        height: 0.0,
+    },
+    instance::SpawnPattern::Sphere {
+        count: NUM_INSTANCES_PER_ROW * NUM_INSTANCES_PER_ROW,
+        radius: 12.0,
+    },
+    instance::SpawnPattern::Random {
+        count: NUM_INSTANCES_PER_ROW * NUM_INSTANCES_PER_ROW,
+        extent: 15.0,
+        seed: 7,
+    },
+];
+// Triangle ratios for the simplified copies of the model, from most to least detailed
+const LOD_RATIOS: [f32; 2] = [0.5, 0.25];
+// How far the closest instance has to be from the camera to use each of `LOD_RATIOS`
+const LOD_DISTANCES: [f32; 2] = [25.0, 50.0];
+// Outline around the picked instance, in model units
+const OUTLINE_THICKNESS: f32 = 0.05;
+const OUTLINE_COLOR: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+// How much + and - change the exposure by, half a stop
+const EXPOSURE_STEP: f32 = std::f32::consts::SQRT_2;
+// The spring between the first 2 instances, it pulls them to half the distance they start at
+const SPRING_STIFFNESS: f32 = 20.0;
+const SPRING_DAMPING: f32 = 2.0;
+// How fast the picked instance turns, in degrees per second
+const SELECTED_SPIN_SPEED: f32 = 90.0;
+// Transparent instances sorted on the GPU, see `pass::transparent`
+const TRANSPARENT_PATTERN: instance::SpawnPattern = instance::SpawnPattern::Sphere {
+    count: 256,
+    radius: 4.0,
+};
+const TRANSPARENT_CENTER: [f32; 3] = [0.0, 8.0, 0.0];
+const TRANSPARENT_COLORS: [[f32; 3]; 3] = [[1.0, 0.3, 0.3], [0.3, 1.0, 0.3], [0.3, 0.3, 1.0]];
+// Lines showing where directional and spot lights point, drawn with the light gizmos
+const LIGHT_DIRECTION_LENGTH: f32 = 3.0;
+// In pixels
+const LIGHT_DIRECTION_THICKNESS: f32 = 3.0;
+// The model every instance draws, also its key in the model cache
+const MODEL_FILE: &str = "banana.obj";
+// A small node hierarchy drawn next to the showcase, see `load_scene_gltf`
+const SCENE_FILE: &str = "scene.gltf";
+// The node in `SCENE_FILE` that keeps turning, carrying its children with it
+const SCENE_SPIN_NODE: &str = "Pivot";
+// Degrees per second
+const SCENE_SPIN_SPEED: f32 = 30.0;
+
+// Field of view limits (in degrees) for Ctrl + scroll zoom
+const MIN_FOVY: f32 = 5.0;
+const MAX_FOVY: f32 = 120.0;
+// How many degrees one notch of the scroll wheel zooms
+const FOVY_PER_SCROLL_LINE: f32 = 5.0;
+// Seconds it takes the zoom to get ~63% of the way to the target
+const ZOOM_SMOOTHING: f32 = 0.1;
+// Closest the camera can scroll to its target
+const MIN_ZOOM_DISTANCE: f32 = 0.5;
+// Fraction of the way the mouse clear color moves toward its target each frame
+const CLEAR_COLOR_SMOOTHING: f64 = 0.05;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -28,7 +109,16 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Projection {
+    Perspective,
+    // Parallel lines stay parallel, `height` is how much of the world fits vertically
+    Orthographic { height: f32 },
+}
+
+#[derive(Clone)]
 struct Camera {
+    projection: Projection,
     eye: cgmath::Point3<f32>,
     target: cgmath::Point3<f32>,
     up: cgmath::Vector3<f32>,
@@ -39,11 +129,85 @@ struct Camera {
 }
 
 impl Camera {
+    fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
     fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let view = self.build_view_matrix();
+        let proj = match self.projection {
+            Projection::Perspective => {
+                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+            }
+            // Depth is linear here, so without remapping OpenGL's -1 to 1 depth range
+            // to wgpu's 0 to 1 the nearer half of the view would get clipped
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                OPENGL_TO_WGPU_MATRIX
+                    * cgmath::ortho(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        self.znear,
+                        self.zfar,
+                    )
+            }
+        };
         proj * view
     }
+
+    // Switches between perspective and orthographic. The orthographic view is sized
+    // so things at the target stay the same size on screen.
+    fn toggle_projection(&mut self) {
+        self.projection = match self.projection {
+            Projection::Perspective => {
+                let distance = (self.target - self.eye).magnitude();
+                Projection::Orthographic {
+                    height: 2.0 * distance * (cgmath::Deg(self.fovy) / 2.0).tan(),
+                }
+            }
+            Projection::Orthographic { .. } => Projection::Perspective,
+        };
+    }
+
+    // Looks straight down at the middle of the instances from 50 units above,
+    // zoomed out far enough to fit all of them
+    fn overview(instances: &[Instance], aspect: f32) -> Self {
+        let count = instances.len().max(1) as f32;
+        let center = instances
+            .iter()
+            .fold(cgmath::Vector3::zero(), |sum, instance| {
+                sum + instance.position
+            })
+            / count;
+        let radius = instances
+            .iter()
+            .map(|instance| {
+                let offset = instance.position - center;
+                (offset.x * offset.x + offset.z * offset.z).sqrt()
+            })
+            .fold(0.0, f32::max);
+        // A bit of margin so the instances on the edge aren't cut in half
+        let size = (radius + 2.0) * 2.0;
+        let center = cgmath::Point3::from_vec(center);
+
+        Self {
+            // Fit the width too when the view is narrower than it is tall
+            projection: Projection::Orthographic {
+                height: size / aspect.min(1.0),
+            },
+            eye: center + cgmath::Vector3::unit_y() * 50.0,
+            target: center,
+            // Looking straight down, so "up" on screen is -Z
+            up: -cgmath::Vector3::unit_z(),
+            aspect,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 200.0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -51,6 +215,8 @@ impl Camera {
 struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    // Only the view part of `view_proj`, for shaders that work in view space
+    view: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -58,6 +224,7 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            view: cgmath::Matrix4::identity().into(),
         }
     }
 
@@ -65,6 +232,58 @@ impl CameraUniform {
         // We're using Vector4 because ofthe camera_uniform 16 byte spacing requirement
         self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.view = camera.build_view_matrix().into();
+    }
+}
+
+// A camera with its own uniform buffer, so several views can be drawn in the same render pass
+// (writing to one buffer between draws would only keep the last write)
+struct ViewCamera {
+    camera: Camera,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ViewCamera {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, camera: Camera) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("View Camera Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("view_camera_bind_group"),
+        });
+
+        Self {
+            camera,
+            uniform,
+            buffer,
+            bind_group,
+        }
+    }
+
+    fn set_aspect(&mut self, queue: &wgpu::Queue, aspect: f32) {
+        self.camera.aspect = aspect;
+        self.write_uniform(queue);
+    }
+
+    fn set_camera(&mut self, queue: &wgpu::Queue, camera: Camera) {
+        self.camera = camera;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&mut self, queue: &wgpu::Queue) {
+        self.uniform.update_view_proj(&self.camera);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
     }
 }
 
@@ -76,6 +295,20 @@ struct CameraController {
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    modifiers: ModifiersState,
+    // Scroll lines since the last update (Ctrl + scroll zooms the lens)
+    zoom_scroll: f32,
+    // Scroll lines since the last update that move the camera towards the target
+    pending_scroll: f32,
+    // Multiplies `speed` for each scroll line
+    zoom_speed: f32,
+    // The field of view we're animating towards
+    target_fovy: Option<f32>,
+    // Where the camera is easing towards, picked up from the camera on the first update
+    target_eye: Option<cgmath::Point3<f32>>,
+    target_target: Option<cgmath::Point3<f32>>,
+    // 0.0 snaps straight to the target, 1.0 never moves
+    smooth_factor: f32,
 }
 
 impl CameraController {
@@ -88,6 +321,14 @@ impl CameraController {
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            modifiers: ModifiersState::empty(),
+            zoom_scroll: 0.0,
+            pending_scroll: 0.0,
+            zoom_speed: 5.0,
+            target_fovy: None,
+            target_eye: None,
+            target_target: None,
+            smooth_factor: 0.8,
         }
     }
 
@@ -131,191 +372,721 @@ impl CameraController {
                     _ => false,
                 }
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => self.process_scroll(delta),
             _ => false,
         }
     }
 
-    fn update_camera(&self, camera: &mut Camera) {
-        let forward = camera.target - camera.eye;
+    // Scrolling moves the camera towards the target,
+    // Ctrl + scroll zooms by changing the field of view instead
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) -> bool {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => *y,
+            // Touchpads scroll in pixels, roughly 20 of them to a line
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+        };
+        if self.modifiers.ctrl() {
+            self.zoom_scroll += lines;
+        } else {
+            self.pending_scroll += lines;
+        }
+        true
+    }
+
+    // Forgets where the camera was heading, e.g. after jumping to a different viewpoint
+    fn reset(&mut self) {
+        self.zoom_scroll = 0.0;
+        self.pending_scroll = 0.0;
+        self.target_fovy = None;
+        self.target_eye = None;
+        self.target_target = None;
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        // Scrolling up zooms in (narrower field of view)
+        let target_fovy = self.target_fovy.unwrap_or(camera.fovy);
+        let target_fovy =
+            (target_fovy - self.zoom_scroll * FOVY_PER_SCROLL_LINE).clamp(MIN_FOVY, MAX_FOVY);
+        self.zoom_scroll = 0.0;
+        self.target_fovy = Some(target_fovy);
+
+        // Ease towards the target so the zoom doesn't jump, independent of frame rate
+        camera.fovy += (target_fovy - camera.fovy) * (1.0 - (-dt / ZOOM_SMOOTHING).exp());
+
+        // Input moves where the camera is heading, not the camera itself
+        let mut eye = self.target_eye.unwrap_or(camera.eye);
+        let target = self.target_target.unwrap_or(camera.target);
+
+        let forward = target - eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
+        // Scrolling up moves in, but never through the target
+        if self.pending_scroll != 0.0 {
+            let distance = (self.pending_scroll * self.speed * self.zoom_speed)
+                .min(forward_mag - MIN_ZOOM_DISTANCE);
+            eye += forward_norm * distance;
+            self.pending_scroll = 0.0;
+        }
+        let forward = target - eye;
+        let forward_mag = forward.magnitude();
+
         // Prevents glitching when camera gets too close to the
         // center of the scene.
         if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+            eye += forward_norm * self.speed;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            eye -= forward_norm * self.speed;
         }
 
         let right = forward_norm.cross(camera.up);
 
         // Redo radius calc in case the up/ down is pressed.
-        let forward = camera.target - camera.eye;
+        let forward = target - eye;
         let forward_mag = forward.magnitude();
 
         if self.is_right_pressed {
             // Rescale the distance between the target and eye so
             // that it doesn't change. The eye therefore still
             // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            eye = target - (forward + right * self.speed).normalize() * forward_mag;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            eye = target - (forward - right * self.speed).normalize() * forward_mag;
         }
+        self.target_eye = Some(eye);
+        self.target_target = Some(target);
+
+        // `smooth_factor` is how much of the distance is left after a frame at 60fps,
+        // raised to the number of those frames that actually passed
+        let t = 1.0 - self.smooth_factor.powf(dt * 60.0);
+        camera.eye += (eye - camera.eye) * t;
+        camera.target += (target - camera.target) * t;
     }
 }
 
-// Instances
-// Lets us duplicate objects in a scene with less cost
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
+// Stored as a `u32` in `LightUniform::light_type`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LightType {
+    // Shines out in every direction from `position`
+    Point = 0,
+    // Shines along `direction` everywhere, like the sun
+    Directional = 1,
+    // Shines from `position` along `direction` in a cone
+    Spot = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    position: [f32; 3],
+    // Fits in the padding after `position`
+    light_type: u32,
+    color: [f32; 3],
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding2: u32,
+    // Which way the light travels, used by directional and spot lights
+    direction: [f32; 3],
+    // Cosines of the spot light cone angles. Full brightness inside the inner cone,
+    // fading out to nothing at the outer one.
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding3: [u32; 3],
 }
 
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        let model =
-            cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation);
-        InstanceRaw {
-            model: model.into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+impl LightUniform {
+    // A point light
+    fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position,
+            light_type: LightType::Point as u32,
+            color,
+            _padding2: 0,
+            direction: [0.0, -1.0, 0.0],
+            inner_cutoff: 0.0,
+            outer_cutoff: 0.0,
+            _padding3: [0; 3],
         }
     }
-}
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
-    normal: [[f32; 3]; 3],
-}
-
-impl model::Vertex for InstanceRaw {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We don't have to do this in code though.
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
-                    shader_location: 9,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
-                    shader_location: 10,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
-                    shader_location: 11,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
+    fn directional(direction: [f32; 3], color: [f32; 3]) -> Self {
+        let direction = cgmath::Vector3::from(direction).normalize();
+        Self {
+            // Directional lights don't have a position, but the light model and gizmo
+            // need somewhere to go, so put them back where the light comes from
+            position: (direction * -10.0).into(),
+            light_type: LightType::Directional as u32,
+            direction: direction.into(),
+            ..Self::new([0.0; 3], color)
+        }
+    }
+
+    // What the light sees when rendering its shadow map, `aspect` is the map's width / height.
+    // Point lights shine every way, so they'd need a cube map instead and return `None`.
+    fn shadow_view_proj(&self, aspect: f32) -> Option<cgmath::Matrix4<f32>> {
+        let direction = cgmath::Vector3::from(self.direction);
+        // The view matrix breaks down when looking straight along `up`
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        if self.light_type == LightType::Directional as u32 {
+            // Covers the middle of the scene, from far enough back that nothing gets clipped
+            let eye = cgmath::Point3::from_vec(direction * -30.0);
+            let view = cgmath::Matrix4::look_to_rh(eye, direction, up);
+            let proj = cgmath::ortho(-20.0 * aspect, 20.0 * aspect, -20.0, 20.0, 0.1, 60.0);
+            Some(OPENGL_TO_WGPU_MATRIX * proj * view)
+        } else if self.light_type == LightType::Spot as u32 {
+            let view = cgmath::Matrix4::look_to_rh(self.position.into(), direction, up);
+            let fovy = cgmath::Rad(self.outer_cutoff.acos() * 2.0);
+            let proj = cgmath::perspective(fovy, aspect, 0.1, 50.0);
+            Some(OPENGL_TO_WGPU_MATRIX * proj * view)
+        } else {
+            None
+        }
+    }
+
+    // `inner_deg` and `outer_deg` are measured from the middle of the cone to its edge
+    fn spot(
+        position: [f32; 3],
+        direction: [f32; 3],
+        inner_deg: f32,
+        outer_deg: f32,
+        color: [f32; 3],
+    ) -> Self {
+        Self {
+            light_type: LightType::Spot as u32,
+            direction: cgmath::Vector3::from(direction).normalize().into(),
+            inner_cutoff: cgmath::Deg(inner_deg).cos(),
+            outer_cutoff: cgmath::Deg(outer_deg).cos(),
+            ..Self::new(position, color)
         }
     }
 }
 
+// The lights buffer starts with this, followed by room for `PhongConfig::max_lights` lights
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct LightUniform {
-    position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    _padding: u32,
-    color: [f32; 3],
+struct LightsHeader {
+    // How many of the lights are in use
+    count: u32,
     // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    _padding2: u32,
+    _padding: [u32; 3],
+}
+
+fn lights_buffer_size(max_lights: usize) -> wgpu::BufferAddress {
+    (std::mem::size_of::<LightsHeader>() + max_lights * std::mem::size_of::<LightUniform>())
+        as wgpu::BufferAddress
+}
+
+fn write_lights_buffer(queue: &wgpu::Queue, buffer: &wgpu::Buffer, lights: &[LightUniform]) {
+    let header = LightsHeader {
+        count: lights.len() as u32,
+        _padding: [0; 3],
+    };
+    queue.write_buffer(buffer, 0, bytemuck::bytes_of(&header));
+    if !lights.is_empty() {
+        queue.write_buffer(
+            buffer,
+            std::mem::size_of::<LightsHeader>() as wgpu::BufferAddress,
+            bytemuck::cast_slice(lights),
+        );
+    }
+}
+
+// A line out of every directional and spot light, showing which way it shines
+fn light_direction_lines(lights: &[LightUniform]) -> Vec<pass::thick_line::ThickLine> {
+    lights
+        .iter()
+        .filter(|light| light.light_type != LightType::Point as u32)
+        .map(|light| {
+            let start = cgmath::Vector3::from(light.position);
+            let end = start + cgmath::Vector3::from(light.direction) * LIGHT_DIRECTION_LENGTH;
+            let [r, g, b] = light.color;
+            pass::thick_line::ThickLine::new(
+                start.into(),
+                end.into(),
+                LIGHT_DIRECTION_THICKNESS,
+                [r, g, b, 1.0],
+            )
+        })
+        .collect()
+}
+
+// `count` colors going once around the color wheel
+fn rainbow_colors(count: usize) -> Vec<[f32; 4]> {
+    (0..count)
+        .map(|i| {
+            let hue = i as f32 / count as f32 * 2.0 * std::f32::consts::PI;
+            let channel = |offset: f32| 0.5 + 0.5 * (hue - offset).cos();
+            [
+                channel(0.0),
+                channel(2.0 * std::f32::consts::FRAC_PI_3),
+                channel(4.0 * std::f32::consts::FRAC_PI_3),
+                1.0,
+            ]
+        })
+        .collect()
+}
+
+// A line per model as `AsyncAssetQueue` gets through them
+fn log_asset_event(event: resources::AssetEvent) {
+    match event {
+        resources::AssetEvent::Loaded {
+            file_name,
+            loaded,
+            total,
+        } => log::info!("Loaded {} ({}/{})", file_name, loaded, total),
+        resources::AssetEvent::Failed {
+            file_name,
+            error,
+            loaded,
+            total,
+        } => log::warn!(
+            "Couldn't load {} ({}/{}): {}",
+            file_name,
+            loaded,
+            total,
+            error
+        ),
+    }
 }
 
 struct State {
     // Graphic context
-    surface: wgpu::Surface,
+    // `None` when rendering headless, frames are only read back with `render_headless`
+    surface: Option<wgpu::Surface>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     // Window size
     size: winit::dpi::PhysicalSize<u32>,
-    // Clear color for mouse interactions
-    clear_color: wgpu::Color,
+    // Latest size from the window, applied at the start of the next update
+    resize_pending: Option<winit::dpi::PhysicalSize<u32>>,
+    // Clear color for mouse interactions, `None` uses the background color
+    clear_color: Option<wgpu::Color>,
+    // Where `clear_color` is heading, set from the cursor position
+    clear_color_target: wgpu::Color,
     // Render pipeline
-    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline: Rc<wgpu::RenderPipeline>,
+    pipeline_cache: pipeline::PipelineCache,
+    pipeline_layouts: ScenePipelineLayouts,
+    // What the scene pipelines were last built with
+    phong_config: PhongConfig,
     // Textures
     depth_texture: texture::Texture,
+    depth_format: texture::DepthFormat,
+    // The scene is drawn here in HDR at `resolution_scaler.render_scale` times the window size,
+    // then `tonemap_pass` maps it to the screen's range and stretches it over the window
+    scene_texture: texture::Texture,
+    tonemap_pass: pass::tonemap::ToneMapPass,
+    resolution_scaler: profiling::DynamicResolutionScaler,
+    depth_prepass: pass::depth_prepass::DepthPrepass,
+    decal_pass: pass::decal::DecalPass,
+    // Only set when `PhongConfig::fog` is
+    fog_pass: Option<pass::fog::FogPass>,
+    // Picking is native only, see `pick`
+    #[cfg(not(target_arch = "wasm32"))]
+    pick_pass: pass::pick::PickPass,
+    background_pass: pass::background::BackgroundPass,
+    // Depth from the first directional and spot lights, for shadows
+    shadow_pass: pass::shadow::ShadowPass,
+    // Size of the shadow maps, see `set_shadow_quality`
+    shadow_quality: pass::shadow::ShadowQuality,
+    // Only set when `PhongConfig::show_light_gizmos` is on
+    light_gizmo_pass: Option<pass::light_gizmo::LightGizmoPass>,
+    // Lines along the light directions, set together with `light_gizmo_pass`.
+    // They're read from a storage buffer, so there are none on the web.
+    light_direction_pass: Option<pass::thick_line::ThickLinePass>,
+    // Only set when `PhongConfig::view_normals` is on
+    normal_pass: Option<pass::normal::NormalPass>,
+    outline_pass: pass::outline::OutlinePass,
+    // Instance that was last picked, it gets outlined
+    selected: Option<usize>,
+    // Last known mouse position in window pixels, for picking
+    #[cfg(not(target_arch = "wasm32"))]
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    #[cfg(not(target_arch = "wasm32"))]
+    hot_reloader: Option<resources::HotReloader>,
     // Camera
     camera: Camera,
     camera_controller: CameraController,
+    // Saved viewpoints, `camera` gets reset to one of these when switching
+    cameras: Vec<(String, Camera)>,
+    active_camera: usize,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
+    // Split screen views. The perspective view uses `camera`,
+    // the top, front and side views each get a fixed camera.
+    viewport_config: viewport::ViewportConfig,
+    editor_cameras: Vec<ViewCamera>,
+    // Top down view for the overview layout
+    overview_camera: ViewCamera,
+    viewport_outline: pass::viewport_outline::ViewportOutlinePass,
     // Instances
     instances: Vec<Instance>,
+    // Index into `SPAWN_PATTERNS` of the current layout
+    spawn_pattern: usize,
     instance_buffer: wgpu::Buffer,
-    // 3D Model
-    obj_model: model::Model,
+    // Instances changed since the last upload, only this span gets written to the GPU
+    dirty_instances: Option<Range<usize>>,
+    // Per instance tint, `None` leaves every instance white
+    instance_colors: Option<Vec<[f32; 4]>>,
+    // Replace `instance_colors` for single instances, keyed by instance index
+    color_overrides: HashMap<usize, ColorOverride>,
+    instance_color_buffer: wgpu::Buffer,
+    // Screenshot waiting for the GPU to finish
+    pending_screenshot: Option<screenshot::Screenshot>,
+    // Physics
+    spring_simulator: physics::SpringSimulator,
+    // Time of the last update, used to get the delta time
+    frame_clock: profiling::FrameClock,
+    // 3D Model, shared with the model cache
+    obj_model: Arc<model::Model>,
+    // Less detailed versions of `obj_model`, without materials of their own
+    lods: Vec<model::Model>,
+    // 0 draws `obj_model`, anything above that `lods[lod_level - 1]`
+    lod_level: usize,
+    // The generated primitives, drawn next to the instances
+    showcase: showcase::Showcase,
+    // Each node is drawn with an instance made from its world transform
+    scene: model::Scene,
+    scene_instance_buffer: wgpu::Buffer,
+    scene_instance_color_buffer: wgpu::Buffer,
     // Lighting
-    light_uniform: LightUniform,
+    // The lights in use, there's room in `light_buffer` for `PhongConfig::max_lights`
+    light_uniforms: Vec<LightUniform>,
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
+    light_render_pipeline: Rc<wgpu::RenderPipeline>,
+    // Drawn once per light by `light_render_pipeline`
+    light_marker: model::DynamicPrimitiveMesh,
+    // Only set when `PhongConfig::vertex_pull` is on
+    vertex_pull_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // Draws `transparent_pass`
+    transparent_pipeline: Rc<wgpu::RenderPipeline>,
+    // Sorted with compute shaders, so there's none on WebGL
+    transparent_pass: Option<pass::transparent::TransparentPass>,
+}
+
+// Options for the main pipeline (Phong unless `shading` says otherwise). Most are decided once
+// at startup, `shading` and `blend_mode` can be switched with `set_shading` / `set_blend_mode`.
+struct PhongConfig {
+    // Room in the lights buffer, and the length of the lights array in the shaders on the web
+    max_lights: usize,
+    // Fetch vertices from a storage buffer using the vertex index instead of a vertex buffer layout
+    vertex_pull: bool,
+    // Draw triangle edges over the shading using barycentric coordinates. Unlike
+    // `PolygonMode::Line` this doesn't need a device feature, so it also works on the web.
+    wireframe_portable: bool,
+    // Flat clear color or a gradient behind the scene
+    background: pass::background::Background,
+    // Pick one with stencil for stencil effects, falls back if the device doesn't support it
+    depth_format: texture::DepthFormat,
+    // Draw a marker where each light is
+    show_light_gizmos: bool,
+    // Render view space normals after the depth prepass, for effects like SSAO
+    view_normals: bool,
+    // Clear to a color that follows the mouse instead of the background color
+    mouse_clear_color: bool,
+    // How the models get combined with the background
+    blend_mode: pipeline::BlendMode,
+    // Distance fog over the finished scene, `None` turns it off
+    fog: Option<pass::fog::Fog>,
+    // Blinn-Phong, or physically based using each material's metallic / roughness
+    shading: pipeline::ShadingModel,
+}
+
+impl Default for PhongConfig {
+    fn default() -> Self {
+        Self {
+            max_lights: 8,
+            vertex_pull: false,
+            wireframe_portable: false,
+            background: Default::default(),
+            depth_format: Default::default(),
+            show_light_gizmos: false,
+            view_normals: false,
+            mouse_clear_color: false,
+            blend_mode: Default::default(),
+            fog: None,
+            shading: Default::default(),
+        }
+    }
+}
+
+// Shaders work in linear space, so an sRGB surface gets the gamma encoding for free on write.
+// If there isn't one the tone map pass does it instead.
+fn preferred_surface_format(formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+    formats
+        .iter()
+        .copied()
+        .find(|format| format.describe().srgb)
+        .unwrap_or(formats[0])
+}
+
+// The `ShadowPass` maps, the first directional light casts one with an orthographic projection
+// and the first spot light one with a perspective projection covering its cone
+const DIRECTIONAL_SHADOW_MAP: usize = 0;
+const SPOT_SHADOW_MAP: usize = 1;
+
+// The lights and the shadow maps they cast, see `light_bind_group_layout`
+fn create_light_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    light_buffer: &wgpu::Buffer,
+    shadow_pass: &pass::shadow::ShadowPass,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(
+                    &shadow_pass.texture(DIRECTIONAL_SHADOW_MAP).view,
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(
+                    &shadow_pass.texture(DIRECTIONAL_SHADOW_MAP).sampler,
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: shadow_pass
+                    .uniform_buffer(DIRECTIONAL_SHADOW_MAP)
+                    .as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(
+                    &shadow_pass.texture(SPOT_SHADOW_MAP).view,
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: shadow_pass
+                    .uniform_buffer(SPOT_SHADOW_MAP)
+                    .as_entire_binding(),
+            },
+        ],
+        label: None,
+    })
+}
+
+fn create_instance_color_buffer(
+    device: &wgpu::Device,
+    instance_colors: &[InstanceColor],
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Instance Color Buffer"),
+        contents: bytemuck::cast_slice(instance_colors),
+        // Copy destination so color overrides can be written without a new buffer
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Layouts for `create_scene_pipelines`, kept so the pipelines can be rebuilt when the config changes
+struct ScenePipelineLayouts {
+    render: wgpu::PipelineLayout,
+    light: wgpu::PipelineLayout,
+    // Only set when vertex pulling is on and available
+    vertex_pull: Option<wgpu::PipelineLayout>,
+}
+
+struct ScenePipelines {
+    render: Rc<wgpu::RenderPipeline>,
+    // `render` with alpha blending, whatever the blend mode is
+    transparent: Rc<wgpu::RenderPipeline>,
+    light: Rc<wgpu::RenderPipeline>,
+    vertex_pull: Option<Rc<wgpu::RenderPipeline>>,
+}
+
+// Every pipeline that depends on the `PhongConfig` or the depth format.
+// Anything built before comes out of `pipeline_cache` instead of being compiled again.
+fn create_scene_pipelines(
+    device: &wgpu::Device,
+    pipeline_cache: &mut pipeline::PipelineCache,
+    layouts: &ScenePipelineLayouts,
+    phong_config: &PhongConfig,
+    depth_format: texture::DepthFormat,
+    light_marker: &model::DynamicPrimitiveMesh,
+) -> ScenePipelines {
+    let fragment_entry_point = phong_config
+        .shading
+        .fragment_entry_point(phong_config.wireframe_portable);
+    let render_with_blend = |pipeline_cache: &mut pipeline::PipelineCache,
+                             blend_mode: pipeline::BlendMode| {
+        let shader_source =
+            pipeline::lights_shader_source(include_str!("shader.wgsl"), phong_config.max_lights);
+        let vertex_layouts = [
+            model::ModelVertex::desc(),
+            InstanceRaw::desc(),
+            InstanceColor::desc(),
+        ];
+        let key = pipeline::PipelineKey::new(
+            &shader_source,
+            &(
+                texture::Texture::HDR_FORMAT,
+                depth_format.format(),
+                &vertex_layouts,
+                true,
+                fragment_entry_point,
+                blend_mode,
+            ),
+        );
+        pipeline_cache.get_or_create(key, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Shader"),
+                source: wgpu::ShaderSource::Wgsl(shader_source.clone()),
+            });
+            create_render_pipeline(
+                device,
+                &layouts.render,
+                Some(depth_format.format()),
+                wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &vertex_layouts,
+                },
+                &shader,
+                fragment_entry_point,
+                true,
+                Some(blend_mode.blend_state()),
+            )
+        })
+    };
+    let render = render_with_blend(pipeline_cache, phong_config.blend_mode);
+    let transparent = render_with_blend(pipeline_cache, pipeline::BlendMode::AlphaBlend);
+
+    let light = {
+        let shader_source =
+            pipeline::lights_shader_source(include_str!("light.wgsl"), phong_config.max_lights);
+        let vertex_layouts = [light_marker.vertices.desc()];
+        let key = pipeline::PipelineKey::new(
+            &shader_source,
+            &(
+                texture::Texture::HDR_FORMAT,
+                depth_format.format(),
+                &vertex_layouts,
+                false,
+            ),
+        );
+        pipeline_cache.get_or_create(key, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(shader_source.clone()),
+            });
+            // The light isn't part of the prepass, so it writes its own depth
+            create_render_pipeline(
+                device,
+                &layouts.light,
+                Some(depth_format.format()),
+                wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &vertex_layouts,
+                },
+                &shader,
+                "fs_main",
+                false,
+                None,
+            )
+        })
+    };
+
+    let vertex_pull = layouts.vertex_pull.as_ref().map(|layout| {
+        let vertex_source = include_str!("shaders/vertex_pull.wgsl");
+        let fragment_source =
+            pipeline::lights_shader_source(include_str!("shader.wgsl"), phong_config.max_lights);
+        // Only instances come from a vertex buffer, the shader indexes into the mesh itself
+        let vertex_layouts = [InstanceRaw::desc(), InstanceColor::desc()];
+        let key = pipeline::PipelineKey::new(
+            &format!("{}{}", vertex_source, fragment_source),
+            &(
+                texture::Texture::HDR_FORMAT,
+                depth_format.format(),
+                &vertex_layouts,
+                true,
+                fragment_entry_point,
+                phong_config.blend_mode,
+            ),
+        );
+        pipeline_cache.get_or_create(key, || {
+            let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Vertex Pull Shader"),
+                source: wgpu::ShaderSource::Wgsl(vertex_source.into()),
+            });
+            let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Shader"),
+                source: wgpu::ShaderSource::Wgsl(fragment_source.clone()),
+            });
+            create_render_pipeline(
+                device,
+                layout,
+                Some(depth_format.format()),
+                wgpu::VertexState {
+                    module: &vertex_shader,
+                    entry_point: "vs_main",
+                    buffers: &vertex_layouts,
+                },
+                &fragment_shader,
+                fragment_entry_point,
+                true,
+                Some(phong_config.blend_mode.blend_state()),
+            )
+        })
+    });
+
+    ScenePipelines {
+        render,
+        transparent,
+        light,
+        vertex_pull,
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
+    // The vertex stage can come from a different module than the fragment stage (e.g. vertex pulling)
+    vertex: wgpu::VertexState,
+    fragment_shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    // Depth was already written by a `DepthPrepass`, so only draw what matches it
+    depth_prepassed: bool,
+    // `None` replaces what's in the target
+    blend: Option<wgpu::BlendState>,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: vertex_layouts,
-        },
+        vertex,
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
+            module: fragment_shader,
+            entry_point: fragment_entry_point,
             targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                // Everything in the scene is drawn into the HDR scene texture
+                format: texture::Texture::HDR_FORMAT,
+                blend: Some(blend.unwrap_or(wgpu::BlendState::REPLACE)),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -333,8 +1104,12 @@ fn create_render_pipeline(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_write_enabled: !depth_prepassed,
+            depth_compare: if depth_prepassed {
+                wgpu::CompareFunction::LessEqual
+            } else {
+                wgpu::CompareFunction::Less
+            },
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -351,28 +1126,32 @@ fn create_render_pipeline(
 
 impl State {
     // Initialize the state
-    async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
+    // Pass `None` for the window to render without a surface (see `render_headless`)
+    async fn new(window: Option<&Window>, size: winit::dpi::PhysicalSize<u32>) -> Self {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
+        let surface = window.map(|window| unsafe { instance.create_surface(window) });
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 force_fallback_adapter: false,
             })
             .await
             .unwrap();
 
+        let phong_config = PhongConfig::default();
+        let depth_format = phong_config.depth_format.supported_or_fallback(&adapter);
+
         // Select a device to use
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    // Also ask for the other stencil format if there is one, so it can be switched to
+                    features: depth_format.required_features()
+                        | (adapter.features() & wgpu::Features::DEPTH32FLOAT_STENCIL8),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -387,15 +1166,21 @@ impl State {
             .await
             .unwrap();
 
-        // Config for surface
+        // Config for surface. Headless frames use the same size and format, just without presenting.
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format: match &surface {
+                Some(surface) => preferred_surface_format(&surface.get_supported_formats(&adapter)),
+                // sRGB so the tone mapped output gets gamma encoded like it would on screen
+                None => wgpu::TextureFormat::Rgba8UnormSrgb,
+            },
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
         };
-        surface.configure(&device, &config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &config);
+        }
 
         // Bind the texture to the renderer
         // This creates a general texture bind group
@@ -418,6 +1203,68 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // Lightmap
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Atlas rect for the diffuse texture, shininess and the PBR parameters
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Normal map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Specular map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
@@ -425,6 +1272,7 @@ impl State {
         // Bind the camera to the shaders
 
         let camera = Camera {
+            projection: Projection::Perspective,
             eye: (0.0, 5.0, -10.0).into(),
             target: (0.0, 0.0, 0.0).into(),
             up: cgmath::Vector3::unit_y(),
@@ -434,6 +1282,26 @@ impl State {
             zfar: 100.0,
         };
         let camera_controller = CameraController::new(0.2);
+        // Viewpoints to flip between with C
+        let cameras = vec![
+            ("Perspective".to_string(), camera.clone()),
+            (
+                "Top".to_string(),
+                Camera {
+                    eye: (0.0, 15.0, 0.0).into(),
+                    // Looking straight down, so "up" on screen is -Z
+                    up: -cgmath::Vector3::unit_z(),
+                    ..camera.clone()
+                },
+            ),
+            (
+                "Front".to_string(),
+                Camera {
+                    eye: (0.0, 0.0, -15.0).into(),
+                    ..camera.clone()
+                },
+            ),
+        ];
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
@@ -445,30 +1313,20 @@ impl State {
         });
 
         // Create instance buffer
-        // We create a 2x2 grid of objects by doing 1 nested loop here
-        // And use the "displacement" matrix above to offset objects with a gap
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
+        // We lay the objects out in a grid with a gap between each one
+        let instances = SPAWN_PATTERNS[0].generate();
+
+        let mut spring_simulator = physics::SpringSimulator::default();
+        if instances.len() >= 2 {
+            let distance = (instances[1].position - instances[0].position).magnitude();
+            spring_simulator.add(physics::SpringConstraint::new(
+                0,
+                1,
+                distance * 0.5,
+                SPRING_STIFFNESS,
+                SPRING_DAMPING,
+            ));
+        }
 
         // We condense the matrix properties into a flat array (aka "raw data")
         // (which is how buffers work - so we can "stride" over chunks)
@@ -477,9 +1335,15 @@ impl State {
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            // Copy source so `readback_instances` can read it back
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         });
 
+        let instance_color_buffer =
+            create_instance_color_buffer(&device, &vec![InstanceColor::WHITE; instances.len()]);
+
         // Create a bind group for camera buffer
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -505,56 +1369,269 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        // Load model from disk or as a HTTP request (for web support)
+        // Cameras for the top, front and side views in the four way layout.
+        // These look from far away with a narrow field of view so they're close to orthographic.
+        let editor_camera = |eye: cgmath::Point3<f32>, up: cgmath::Vector3<f32>| {
+            ViewCamera::new(
+                &device,
+                &camera_bind_group_layout,
+                Camera {
+                    projection: Projection::Perspective,
+                    eye,
+                    target: (0.0, 0.0, 0.0).into(),
+                    up,
+                    aspect: config.width as f32 / config.height as f32,
+                    fovy: 30.0,
+                    znear: 0.1,
+                    zfar: 200.0,
+                },
+            )
+        };
+        let editor_cameras = vec![
+            // Looking straight down, so "up" on screen is -Z
+            editor_camera((0.0, 80.0, 0.0).into(), -cgmath::Vector3::unit_z()),
+            editor_camera((0.0, 0.0, -80.0).into(), cgmath::Vector3::unit_y()),
+            editor_camera((80.0, 0.0, 0.0).into(), cgmath::Vector3::unit_y()),
+        ];
+        let overview_camera = ViewCamera::new(
+            &device,
+            &camera_bind_group_layout,
+            Camera::overview(&instances, config.width as f32 / config.height as f32),
+        );
+        let viewport_config = viewport::ViewportConfig::new(viewport::ViewportLayout::Single);
+        let viewport_outline = pass::viewport_outline::ViewportOutlinePass::new(
+            &device,
+            texture::Texture::HDR_FORMAT,
+            Some(depth_format.format()),
+        );
+
+        // Load models from disk or as a HTTP request (for web support)
+        // All the models load at the same time, starting with the highest priority
         log::warn!("Load model");
-        let obj_model =
-            resources::load_model("banana.obj", &device, &queue, &texture_bind_group_layout)
+        let mut asset_queue = resources::AsyncAssetQueue::default();
+        asset_queue.push(MODEL_FILE, 0);
+        let mut models = asset_queue
+            .load_all(&device, &queue, &texture_bind_group_layout, log_asset_event)
+            .await
+            .into_iter();
+        let mut obj_model = models
+            .next()
+            .unwrap()
+            .expect("Couldn't load model. Maybe path is wrong?");
+
+        // A see-through cloud floating over the instances
+        let transparent_pass = (!cfg!(target_arch = "wasm32")).then(|| {
+            let center = cgmath::Vector3::from(TRANSPARENT_CENTER);
+            let instances = TRANSPARENT_PATTERN
+                .generate()
+                .into_iter()
+                .enumerate()
+                .map(|(i, instance)| {
+                    // Cycle through a few colors so the sort order is easy to see
+                    let [r, g, b] = TRANSPARENT_COLORS[i % TRANSPARENT_COLORS.len()];
+                    let instance = Instance {
+                        position: instance.position + center,
+                        ..instance
+                    };
+                    (
+                        instance,
+                        InstanceColor {
+                            color: [r, g, b, 0.4],
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            pass::transparent::TransparentPass::new(
+                &device,
+                &queue,
+                &texture_bind_group_layout,
+                &camera_buffer,
+                primitives::icosphere::generate_icosphere(
+                    0.5,
+                    2,
+                    primitives::WindingOrder::default(),
+                ),
+                &instances,
+                phong_config.wireframe_portable,
+            )
+        });
+
+        let showcase = showcase::Showcase::new(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            phong_config.wireframe_portable,
+        )
+        .await
+        .expect("Couldn't load the showcase textures");
+
+        let mut scene =
+            resources::load_scene_gltf(SCENE_FILE, &device, &queue, &texture_bind_group_layout)
                 .await
-                .expect("Couldn't load model. Maybe path is wrong?");
+                .expect("Couldn't load the scene");
+        log::info!(
+            "Loaded {} nodes from {}: {:?}",
+            scene.nodes.len(),
+            SCENE_FILE,
+            scene
+                .nodes
+                .iter()
+                .map(|node| &node.name)
+                .collect::<Vec<_>>()
+        );
+        let scene_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scene Instance Buffer"),
+            contents: bytemuck::cast_slice(&scene.instance_data()),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let scene_instance_color_buffer =
+            create_instance_color_buffer(&device, &vec![InstanceColor::WHITE; scene.nodes.len()]);
+
+        let mut model_cache = resources::model_cache().lock().unwrap();
+
+        // Simplified copies for when the instances are far away, see `lod_level`.
+        // Only the meshes, they use `obj_model`'s materials.
+        let mesh_count = obj_model.meshes.len();
+        let mut lod_meshes = obj_model.generate_lods(&LOD_RATIOS, &device).into_iter();
+        let mut lods = LOD_RATIOS
+            .iter()
+            .map(|_| model::Model {
+                meshes: lod_meshes.by_ref().take(mesh_count).collect(),
+                materials: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        // The wireframe needs every triangle to have its own vertices.
+        // Done after the LODs, the simplification needs the triangles to share them.
+        if phong_config.wireframe_portable {
+            let unweld = |mesh: &mut model::Mesh| {
+                let mut vertices = std::mem::take(&mut mesh.vertices);
+                let mut indices = std::mem::take(&mut mesh.indices);
+                primitives::add_barycentric_coordinates(&mut vertices, &mut indices);
+                *mesh = model::Mesh::new(&device, &mesh.name, vertices, indices, mesh.material);
+            };
+            model_cache.edit(MODEL_FILE, &mut obj_model, |obj_model| {
+                obj_model.meshes.iter_mut().for_each(unweld)
+            });
+            lods.iter_mut()
+                .flat_map(|lod| lod.meshes.iter_mut())
+                .chain(scene.model.meshes.iter_mut())
+                .for_each(unweld);
+        }
 
         // Lighting
         // Create light uniforms and setup buffer for them
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
+        let light_uniforms = vec![
+            LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]),
+            // A dim blue fill light from above on the other side
+            LightUniform::directional([0.3, -1.0, 0.5], [0.1, 0.2, 0.5]),
+            // A warm spot light shining straight down on the middle of the scene
+            LightUniform::spot(
+                [0.0, 8.0, 0.0],
+                [0.0, -1.0, 0.0],
+                15.0,
+                25.0,
+                [1.0, 0.6, 0.3],
+            ),
+        ];
+        assert!(
+            light_uniforms.len() <= phong_config.max_lights,
+            "PhongConfig::max_lights is {}, which doesn't fit the {} starting lights",
+            phong_config.max_lights,
+            light_uniforms.len()
+        );
 
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: lights_buffer_size(phong_config.max_lights),
+            usage: pipeline::lights_buffer_usage() | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
+        write_lights_buffer(&queue, &light_buffer, &light_uniforms);
+
+        // The lights' shadows get sampled alongside them
+        let shadow_quality = pass::shadow::ShadowQuality::High;
+        let shadow_config = pass::shadow::ShadowConfig::new(shadow_quality, &device.limits())
+            .expect("Couldn't set up the shadow map");
+        let shadow_pass = pass::shadow::ShadowPass::new(&device, &[shadow_config; 2]);
 
         // Create bind groups for lights
         let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: pipeline::LIGHTS_BINDING_TYPE,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // Shadow map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    // Which light casts the shadow and what it sees
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Same again for the spot light's shadow, it shares the sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
                 label: None,
             });
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
+        let light_bind_group = create_light_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &light_buffer,
+            &shadow_pass,
+        );
 
         // Create depth texture
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &config, depth_format, "depth_texture");
 
         // Create the render pipeline
         let render_pipeline_layout =
@@ -568,44 +1645,195 @@ impl State {
                 ],
                 push_constant_ranges: &[],
             });
-
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
-
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
                 bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
                 push_constant_ranges: &[],
             });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+
+        // Vertex pulling reads the model's vertex buffer as a storage buffer instead of
+        // through a vertex layout. WebGL can't use storage buffers, so it's native only.
+        let vertex_pull_pipeline_layout =
+            if phong_config.vertex_pull && cfg!(target_arch = "wasm32") {
+                log::warn!("Vertex pulling needs storage buffers, falling back to vertex buffers");
+                None
+            } else if phong_config.vertex_pull {
+                let vertex_pull_bind_group_layout =
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("vertex_pull_bind_group_layout"),
+                    });
+                model_cache.edit(MODEL_FILE, &mut obj_model, |obj_model| {
+                    for mesh in obj_model.meshes.iter_mut() {
+                        mesh.enable_vertex_pull(&device, &vertex_pull_bind_group_layout);
+                    }
+                });
+                for mesh in lods.iter_mut().flat_map(|lod| lod.meshes.iter_mut()) {
+                    mesh.enable_vertex_pull(&device, &vertex_pull_bind_group_layout);
+                }
+
+                Some(
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("Vertex Pull Pipeline Layout"),
+                        bind_group_layouts: &[
+                            &texture_bind_group_layout,
+                            &camera_bind_group_layout,
+                            &light_bind_group_layout,
+                            &vertex_pull_bind_group_layout,
+                        ],
+                        push_constant_ranges: &[],
+                    }),
+                )
+            } else {
+                None
             };
-            create_render_pipeline(
+        drop(model_cache);
+        let pipeline_layouts = ScenePipelineLayouts {
+            render: render_pipeline_layout,
+            light: light_pipeline_layout,
+            vertex_pull: vertex_pull_pipeline_layout,
+        };
+
+        // Pipelines only depend on the texture formats (not size), so they never need to be
+        // recreated in `resize`. Changing the config rebuilds them through the cache, which
+        // hands back the same pipeline if we ever ask for an identical configuration again.
+        let mut pipeline_cache = pipeline::PipelineCache::default();
+        // The light shader only reads positions, so the marker leaves out the rest of `ModelVertex`
+        let light_marker = {
+            let (vertices, indices) = primitives::icosphere::generate_icosphere(
+                1.0,
+                1,
+                primitives::WindingOrder::default(),
+            );
+            model::DynamicPrimitiveMesh::new(
                 &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
+                vec![model::VertexAttributeDesc {
+                    offset: 0,
+                    size: 3,
+                    shader_location: 0,
+                }],
+                vertices.iter().flat_map(|vertex| vertex.position).collect(),
+                &indices,
             )
         };
+        let ScenePipelines {
+            render: render_pipeline,
+            transparent: transparent_pipeline,
+            light: light_render_pipeline,
+            vertex_pull: vertex_pull_pipeline,
+        } = create_scene_pipelines(
+            &device,
+            &mut pipeline_cache,
+            &pipeline_layouts,
+            &phong_config,
+            depth_format,
+            &light_marker,
+        );
+
+        // Starts out at full resolution, `update` lowers it if we can't keep up with 60fps
+        let resolution_scaler = profiling::DynamicResolutionScaler::new(1000.0 / 60.0);
+        let scene_texture = texture::Texture::create_render_target(
+            &device,
+            config.width,
+            config.height,
+            texture::Texture::HDR_FORMAT,
+            "scene_texture",
+        );
+        let mut tonemap_pass = pass::tonemap::ToneMapPass::new(&device, config.format);
+        tonemap_pass.set_source(&device, &scene_texture);
+        let depth_prepass = pass::depth_prepass::DepthPrepass::new(
+            &device,
+            &camera_bind_group_layout,
+            depth_format.format(),
+        );
+        let normal_pass = phong_config.view_normals.then(|| {
+            pass::normal::NormalPass::new(
+                &device,
+                &camera_bind_group_layout,
+                depth_format.format(),
+                scene_texture.size.width,
+                scene_texture.size.height,
+            )
+        });
+        let outline_pass = pass::outline::OutlinePass::new(
+            &device,
+            texture::Texture::HDR_FORMAT,
+            depth_format.format(),
+            &camera_bind_group_layout,
+            obj_model.generate_outline_meshes(OUTLINE_THICKNESS, &device),
+            OUTLINE_COLOR,
+        );
+        // One view for each camera that can be on screen at once
+        let mut decal_pass = pass::decal::DecalPass::new(
+            &device,
+            texture::Texture::HDR_FORMAT,
+            1 + editor_cameras.len(),
+        );
+        decal_pass.set_depth(&device, &depth_texture);
+        let fog_pass = phong_config.fog.map(|fog| {
+            let mut fog_pass = pass::fog::FogPass::new(
+                &device,
+                texture::Texture::HDR_FORMAT,
+                1 + editor_cameras.len(),
+                fog,
+            );
+            fog_pass.set_depth(&device, &depth_texture);
+            fog_pass
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        let pick_pass = pass::pick::PickPass::new(&device, &config, &camera_bind_group_layout);
+        let background_pass = pass::background::BackgroundPass::new(
+            &device,
+            texture::Texture::HDR_FORMAT,
+            Some(depth_format.format()),
+            phong_config.background,
+        );
+        let light_gizmo_pass = phong_config.show_light_gizmos.then(|| {
+            pass::light_gizmo::LightGizmoPass::new(
+                &device,
+                texture::Texture::HDR_FORMAT,
+                Some(depth_format.format()),
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                phong_config.max_lights,
+            )
+        });
+        let light_direction_pass =
+            (phong_config.show_light_gizmos && !cfg!(target_arch = "wasm32")).then(|| {
+                let mut light_direction_pass = pass::thick_line::ThickLinePass::new(
+                    &device,
+                    texture::Texture::HDR_FORMAT,
+                    Some(depth_format.format()),
+                    &camera_bind_group_layout,
+                    scene_texture.size.width,
+                    scene_texture.size.height,
+                );
+                light_direction_pass.set_lines(
+                    &device,
+                    &queue,
+                    &light_direction_lines(&light_uniforms),
+                );
+                light_direction_pass
+            });
 
         // Clear color used for mouse input interaction
-        let clear_color = wgpu::Color::BLACK;
+        let clear_color = phong_config.mouse_clear_color.then_some(wgpu::Color::BLACK);
+
+        // Watch the assets folder so textures update when they're saved
+        #[cfg(not(target_arch = "wasm32"))]
+        let hot_reloader = resources::HotReloader::new()
+            .map_err(|e| log::warn!("Texture hot reload disabled: {}", e))
+            .ok();
 
         Self {
             surface,
@@ -613,45 +1841,535 @@ impl State {
             queue,
             config,
             clear_color,
+            clear_color_target: wgpu::Color::BLACK,
             size,
+            resize_pending: None,
             render_pipeline,
+            pipeline_cache,
+            pipeline_layouts,
+            phong_config,
             depth_texture,
+            depth_format,
+            scene_texture,
+            tonemap_pass,
+            resolution_scaler,
+            depth_prepass,
+            decal_pass,
+            fog_pass,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
+            light_bind_group_layout,
+            #[cfg(not(target_arch = "wasm32"))]
+            hot_reloader,
             camera,
             camera_controller,
+            cameras,
+            active_camera: 0,
             camera_buffer,
             camera_bind_group,
             camera_uniform,
+            viewport_config,
+            editor_cameras,
+            overview_camera,
+            viewport_outline,
             instances,
+            spawn_pattern: 0,
             instance_buffer,
+            dirty_instances: None,
+            instance_colors: None,
+            color_overrides: HashMap::new(),
+            instance_color_buffer,
+            pending_screenshot: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            pick_pass,
+            background_pass,
+            shadow_pass,
+            shadow_quality,
+            light_gizmo_pass,
+            light_direction_pass,
+            normal_pass,
+            outline_pass,
+            selected: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            spring_simulator,
+            frame_clock: profiling::FrameClock::new(),
             obj_model,
-            light_uniform,
+            lods,
+            lod_level: 0,
+            showcase,
+            scene,
+            scene_instance_buffer,
+            scene_instance_color_buffer,
+            light_uniforms,
             light_buffer,
             light_bind_group,
             light_render_pipeline,
+            light_marker,
+            vertex_pull_pipeline,
+            transparent_pipeline,
+            transparent_pass,
         }
     }
 
-    // Keeps state in sync with window size when changed
+    // Keeps state in sync with window size when changed.
+    // Live resizing sends lots of these per frame, so we only keep the latest
+    // and let `update` apply it once, instead of recreating the depth texture every time.
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.resize_pending = Some(new_size);
+    }
+
+    fn apply_pending_resize(&mut self) {
+        let new_size = match self.resize_pending.take() {
+            Some(new_size) => new_size,
+            None => return,
+        };
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            self.pick_pass.resize(&self.device, &self.config);
             // Make sure to current window size to depth texture - required for calc
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            // (pipelines don't reference the surface size, so they can stay as they are)
+            self.resize_render_targets();
+
+            self.update_view_aspects();
+        }
+    }
+
+    // Replaces the light at `index`, adding it (and unlit ones before it) if it's past the end.
+    // Fails if there isn't room for it, see `PhongConfig::max_lights`.
+    fn update_light(&mut self, index: usize, light: LightUniform) -> anyhow::Result<()> {
+        let max_lights = self.phong_config.max_lights;
+        if index >= max_lights {
+            anyhow::bail!(
+                "Can't set light {}, PhongConfig::max_lights only has room for {}",
+                index,
+                max_lights
+            );
+        }
+        if index >= self.light_uniforms.len() {
+            self.light_uniforms
+                .resize(index + 1, LightUniform::new([0.0; 3], [0.0; 3]));
+        }
+        self.light_uniforms[index] = light;
+        self.write_lights();
+        Ok(())
+    }
+
+    fn write_lights(&mut self) {
+        write_lights_buffer(&self.queue, &self.light_buffer, &self.light_uniforms);
+        if let Some(light_direction_pass) = &mut self.light_direction_pass {
+            light_direction_pass.set_lines(
+                &self.device,
+                &self.queue,
+                &light_direction_lines(&self.light_uniforms),
+            );
+        }
+    }
+
+    // Moves the main view to one of the saved viewpoints
+    fn set_active_camera(&mut self, index: usize) {
+        self.active_camera = index;
+        let aspect = self.camera.aspect;
+        self.camera = self.cameras[index].1.clone();
+        // The saved camera might be from before the last resize
+        self.camera.aspect = aspect;
+        // Otherwise it would ease back to where the last camera was heading
+        self.camera_controller.reset();
+    }
+
+    // Cameras for every view after the main one in the current layout, in the same order as the rects
+    fn secondary_views(&self) -> &[ViewCamera] {
+        match self.viewport_config.layout {
+            viewport::ViewportLayout::Single => &[],
+            viewport::ViewportLayout::FourWay => &self.editor_cameras,
+            viewport::ViewportLayout::Overview => std::slice::from_ref(&self.overview_camera),
         }
     }
 
+    // Matches each camera to the shape of its view, needed after a resize or layout change
+    fn update_view_aspects(&mut self) {
+        let rects = self
+            .viewport_config
+            .rects(self.config.width, self.config.height);
+        // The main camera's uniform gets rebuilt every update
+        self.camera.aspect = rects[0].aspect();
+        let secondary_views = match self.viewport_config.layout {
+            viewport::ViewportLayout::Single => &mut [],
+            viewport::ViewportLayout::FourWay => self.editor_cameras.as_mut_slice(),
+            viewport::ViewportLayout::Overview => std::slice::from_mut(&mut self.overview_camera),
+        };
+        for (view_camera, rect) in secondary_views.iter_mut().zip(&rects[1..]) {
+            view_camera.set_aspect(&self.queue, rect.aspect());
+        }
+    }
+
+    // Recreate the offscreen scene and depth textures at the current render scale
+    fn resize_render_targets(&mut self) {
+        let (width, height) = self
+            .resolution_scaler
+            .render_size(self.config.width, self.config.height);
+        let mut scaled_config = self.config.clone();
+        scaled_config.width = width;
+        scaled_config.height = height;
+
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &scaled_config,
+            self.depth_format,
+            "depth_texture",
+        );
+        self.scene_texture = texture::Texture::create_render_target(
+            &self.device,
+            width,
+            height,
+            texture::Texture::HDR_FORMAT,
+            "scene_texture",
+        );
+        self.tonemap_pass
+            .set_source(&self.device, &self.scene_texture);
+        self.decal_pass.set_depth(&self.device, &self.depth_texture);
+        if let Some(fog_pass) = &mut self.fog_pass {
+            fog_pass.set_depth(&self.device, &self.depth_texture);
+        }
+        if let Some(normal_pass) = &mut self.normal_pass {
+            normal_pass.resize(&self.device, width, height);
+        }
+        if let Some(light_direction_pass) = &self.light_direction_pass {
+            light_direction_pass.resize(&self.queue, width, height);
+        }
+    }
+
+    // Call after changing `phong_config` or `depth_format`
+    fn rebuild_pipelines(&mut self) {
+        let pipelines = create_scene_pipelines(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.pipeline_layouts,
+            &self.phong_config,
+            self.depth_format,
+            &self.light_marker,
+        );
+        self.render_pipeline = pipelines.render;
+        self.transparent_pipeline = pipelines.transparent;
+        self.light_render_pipeline = pipelines.light;
+        self.vertex_pull_pipeline = pipelines.vertex_pull;
+    }
+
+    pub fn set_shading(&mut self, shading: pipeline::ShadingModel) {
+        self.phong_config.shading = shading;
+        self.rebuild_pipelines();
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: pipeline::BlendMode) {
+        self.phong_config.blend_mode = blend_mode;
+        self.rebuild_pipelines();
+    }
+
+    // Everything that tests against the depth buffer gets recreated to match the new format
+    pub fn set_depth_format(&mut self, depth_format: texture::DepthFormat) {
+        if !self
+            .device
+            .features()
+            .contains(depth_format.required_features())
+        {
+            log::warn!("{:?} isn't supported on this device", depth_format);
+            return;
+        }
+        self.depth_format = depth_format;
+        self.resize_render_targets();
+
+        let format = depth_format.format();
+        self.depth_prepass = pass::depth_prepass::DepthPrepass::new(
+            &self.device,
+            &self.camera_bind_group_layout,
+            format,
+        );
+        if let Some(normal_pass) = &mut self.normal_pass {
+            *normal_pass = pass::normal::NormalPass::new(
+                &self.device,
+                &self.camera_bind_group_layout,
+                format,
+                self.scene_texture.size.width,
+                self.scene_texture.size.height,
+            );
+        }
+        self.viewport_outline = pass::viewport_outline::ViewportOutlinePass::new(
+            &self.device,
+            texture::Texture::HDR_FORMAT,
+            Some(format),
+        );
+        self.background_pass = pass::background::BackgroundPass::new(
+            &self.device,
+            texture::Texture::HDR_FORMAT,
+            Some(format),
+            self.background_pass.background(),
+        );
+        self.outline_pass.set_depth_format(&self.device, format);
+        self.rebuild_pipelines();
+        // Recreated for the new depth format
+        self.set_show_light_gizmos(self.phong_config.show_light_gizmos);
+    }
+
+    // Markers where the lights are, with lines showing which way they point (not on the web)
+    pub fn set_show_light_gizmos(&mut self, show: bool) {
+        self.phong_config.show_light_gizmos = show;
+        self.light_gizmo_pass = show.then(|| {
+            pass::light_gizmo::LightGizmoPass::new(
+                &self.device,
+                texture::Texture::HDR_FORMAT,
+                Some(self.depth_format.format()),
+                &self.camera_bind_group_layout,
+                &self.light_bind_group_layout,
+                self.phong_config.max_lights,
+            )
+        });
+        self.light_direction_pass = (show && !cfg!(target_arch = "wasm32")).then(|| {
+            pass::thick_line::ThickLinePass::new(
+                &self.device,
+                texture::Texture::HDR_FORMAT,
+                Some(self.depth_format.format()),
+                &self.camera_bind_group_layout,
+                self.scene_texture.size.width,
+                self.scene_texture.size.height,
+            )
+        });
+        self.write_lights();
+    }
+
+    // Recreates the shadow maps at the new size. Fails if the device can't make one that big,
+    // the current one stays then.
+    pub fn set_shadow_quality(
+        &mut self,
+        quality: pass::shadow::ShadowQuality,
+    ) -> anyhow::Result<()> {
+        let config = pass::shadow::ShadowConfig::new(quality, &self.device.limits())?;
+        self.shadow_pass = pass::shadow::ShadowPass::new(&self.device, &[config; 2]);
+        self.light_bind_group = create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.light_buffer,
+            &self.shadow_pass,
+        );
+        self.shadow_quality = quality;
+        Ok(())
+    }
+
+    // `None` turns the fog off
+    pub fn set_fog(&mut self, fog: Option<pass::fog::Fog>) {
+        let fog = match fog {
+            Some(fog) => fog,
+            None => {
+                self.fog_pass = None;
+                return;
+            }
+        };
+        if let Some(fog_pass) = &mut self.fog_pass {
+            fog_pass.set_fog(fog, &self.queue);
+            return;
+        }
+        let mut fog_pass = pass::fog::FogPass::new(
+            &self.device,
+            texture::Texture::HDR_FORMAT,
+            1 + self.editor_cameras.len(),
+            fog,
+        );
+        fog_pass.set_depth(&self.device, &self.depth_texture);
+        self.fog_pass = Some(fog_pass);
+    }
+
+    // Changes to the returned instance show up on the next frame
+    pub fn instance_mut(&mut self, index: usize) -> &mut Instance {
+        self.mark_instances_dirty(index..index + 1);
+        &mut self.instances[index]
+    }
+
+    // For changes made to `instances` directly
+    pub fn mark_instances_dirty(&mut self, range: Range<usize>) {
+        self.dirty_instances = Some(match self.dirty_instances.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    // Replaces every instance. The buffer is only recreated if the count changed.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let instance_count = instances.len();
+        self.selected = self.selected.filter(|&index| index < instance_count);
+        // Keep the whole scene in the overview
+        let aspect = self.overview_camera.camera.aspect;
+        self.overview_camera
+            .set_camera(&self.queue, Camera::overview(&instances, aspect));
+
+        if instances.len() == self.instances.len() {
+            self.instances = instances;
+            self.mark_instances_dirty(0..self.instances.len());
+            return;
+        }
+
+        self.instances = instances;
+        let instance_data = self
+            .instances
+            .iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        self.instance_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(&instance_data),
+                usage: wgpu::BufferUsages::VERTEX
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+            });
+        // Already has the latest data
+        self.dirty_instances = None;
+        self.color_overrides
+            .retain(|&index, _| index < instance_count);
+        self.instance_color_buffer =
+            create_instance_color_buffer(&self.device, &self.instance_color_data());
+    }
+
+    // Tints each instance. Instances past the end of `colors` (or all of them with `None`) stay white.
+    pub fn set_instance_colors(&mut self, colors: Option<Vec<[f32; 4]>>) {
+        self.instance_colors = colors;
+        self.write_instance_colors();
+    }
+
+    // Shows `color` on one instance instead of its own color, `None` removes the override
+    pub fn set_color_override(&mut self, index: usize, color_override: Option<ColorOverride>) {
+        match color_override {
+            Some(color_override) => self.color_overrides.insert(index, color_override),
+            None => self.color_overrides.remove(&index),
+        };
+        self.write_instance_colors();
+    }
+
+    // Overrides an instance's color for the next `frames` frames
+    pub fn flash_color(&mut self, index: usize, color: [f32; 4], frames: u32) {
+        self.set_color_override(
+            index,
+            Some(ColorOverride {
+                color,
+                frames_left: Some(frames),
+            }),
+        );
+    }
+
+    // Counts down timed overrides, once per frame
+    fn tick_color_overrides(&mut self) {
+        // Expired ones are removed a frame after reaching 0, so they're shown for every frame counted
+        let count = self.color_overrides.len();
+        self.color_overrides
+            .retain(|_, color_override| color_override.frames_left != Some(0));
+        for color_override in self.color_overrides.values_mut() {
+            if let Some(frames_left) = &mut color_override.frames_left {
+                *frames_left -= 1;
+            }
+        }
+        if self.color_overrides.len() != count {
+            self.write_instance_colors();
+        }
+    }
+
+    // One color for each instance, padded with white if there aren't enough
+    fn instance_color_data(&self) -> Vec<InstanceColor> {
+        let colors = self.instance_colors.as_deref().unwrap_or(&[]);
+        (0..self.instances.len())
+            .map(|i| {
+                if let Some(color_override) = self.color_overrides.get(&i) {
+                    return InstanceColor {
+                        color: color_override.color,
+                    };
+                }
+                match colors.get(i) {
+                    Some(&color) => InstanceColor { color },
+                    None => InstanceColor::WHITE,
+                }
+            })
+            .collect()
+    }
+
+    fn write_instance_colors(&self) {
+        self.queue.write_buffer(
+            &self.instance_color_buffer,
+            0,
+            bytemuck::cast_slice(&self.instance_color_data()),
+        );
+    }
+
+    fn upload_dirty_instances(&mut self) {
+        let dirty = match self.dirty_instances.take() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+        let dirty = dirty.start..dirty.end.min(self.instances.len());
+        if dirty.is_empty() {
+            return;
+        }
+
+        let instance_data = self.instances[dirty.clone()]
+            .iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        let offset = (dirty.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            offset,
+            bytemuck::cast_slice(&instance_data),
+        );
+    }
+
+    // Projects a texture onto the scene, see `pass::decal::Decal`
+    pub fn add_decal(&mut self, decal: pass::decal::Decal) {
+        self.decal_pass.add(&self.device, decal);
+    }
+
     // Handle input using WindowEvent
     fn input(&mut self, event: &WindowEvent) -> bool {
         // Send any input to camera controller
         self.camera_controller.process_events(event);
 
         match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.key_pressed(*key),
+            // The result shows up a few frames later, see `poll_pick`
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.pick(self.cursor_position.x as u32, self.cursor_position.y as u32);
+                true
+            }
             WindowEvent::CursorMoved { position, .. } => {
-                self.clear_color = wgpu::Color {
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    self.cursor_position = *position;
+                }
+                if let Some(active) = self.viewport_config.viewport_at(
+                    self.size.width,
+                    self.size.height,
+                    position.x,
+                    position.y,
+                ) {
+                    self.viewport_config.active = active;
+                }
+
+                self.clear_color_target = wgpu::Color {
                     r: 0.0,
                     g: position.y as f64 / self.size.height as f64,
                     b: position.x as f64 / self.size.width as f64,
@@ -663,32 +2381,403 @@ impl State {
         }
     }
 
+    // Returns false for keys that don't do anything, the same as `input`
+    fn key_pressed(&mut self, key: VirtualKeyCode) -> bool {
+        match key {
+            VirtualKeyCode::F12 => {
+                if let Err(e) = self.take_screenshot("screenshot.png") {
+                    log::warn!("Couldn't take screenshot: {}", e);
+                }
+            }
+            // Cycle through the tone mapping curves
+            VirtualKeyCode::T => {
+                let operator = self.tonemap_pass.operator().next();
+                self.tonemap_pass.set_operator(operator, &self.queue);
+                log::info!("Tone mapping: {:?}", operator);
+            }
+            // Drop a white point light where the camera is, until there's no room left
+            VirtualKeyCode::L => {
+                let light = LightUniform::new(self.camera.eye.into(), [1.0, 1.0, 1.0]);
+                if let Err(e) = self.update_light(self.light_uniforms.len(), light) {
+                    log::warn!("Couldn't add a light: {}", e);
+                }
+            }
+            // Switch between Blinn-Phong and PBR shading
+            VirtualKeyCode::M => {
+                self.set_shading(self.phong_config.shading.next());
+                log::info!("Shading: {:?}", self.phong_config.shading);
+            }
+            // Show or hide where the lights are and which way they point
+            VirtualKeyCode::H => self.set_show_light_gizmos(!self.phong_config.show_light_gizmos),
+            // Jump to the next saved viewpoint
+            VirtualKeyCode::C => {
+                if !self.cameras.is_empty() {
+                    self.set_active_camera((self.active_camera + 1) % self.cameras.len());
+                    log::info!("Camera: {}", self.cameras[self.active_camera].0);
+                }
+            }
+            // Flatten the main view for lining things up
+            VirtualKeyCode::P => {
+                self.camera.toggle_projection();
+                log::info!("Projection: {:?}", self.camera.projection);
+            }
+            // Switch between a single view and the four way editor layout
+            VirtualKeyCode::Tab => {
+                self.viewport_config.toggle_layout();
+                self.update_view_aspects();
+            }
+            // Lay the instances out differently
+            VirtualKeyCode::I => {
+                self.spawn_pattern = (self.spawn_pattern + 1) % SPAWN_PATTERNS.len();
+                let pattern = SPAWN_PATTERNS[self.spawn_pattern];
+                self.set_instances(pattern.generate());
+                log::info!("Spawn pattern: {:?}", pattern);
+            }
+            // Brighten or darken the tone mapped image
+            VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                let exposure = self.tonemap_pass.exposure() * EXPOSURE_STEP;
+                self.tonemap_pass.set_exposure(exposure, &self.queue);
+                log::info!("Exposure: {:.2}", exposure);
+            }
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                let exposure = self.tonemap_pass.exposure() / EXPOSURE_STEP;
+                self.tonemap_pass.set_exposure(exposure, &self.queue);
+                log::info!("Exposure: {:.2}", exposure);
+            }
+            // Stamp a decal onto whatever is around the camera target
+            VirtualKeyCode::V => {
+                // A 2 unit box around the target, projecting straight down
+                let world_transform =
+                    cgmath::Matrix4::from_translation(self.camera.target.to_vec())
+                        * cgmath::Matrix4::from_angle_x(cgmath::Deg(90.0))
+                        * cgmath::Matrix4::from_scale(2.0)
+                        * cgmath::Matrix4::from_translation((-0.5, -0.5, -0.5).into());
+                let texture = texture::Texture::from_color(
+                    &self.device,
+                    &self.queue,
+                    [200, 40, 40, 255],
+                    "decal_texture",
+                );
+                self.add_decal(pass::decal::Decal {
+                    world_transform,
+                    texture: Rc::new(texture),
+                });
+            }
+            // Remove every decal
+            VirtualKeyCode::Back => self.decal_pass.clear(),
+            // Switch between a plain background and a gradient
+            VirtualKeyCode::G => {
+                let background = match self.background_pass.background() {
+                    pass::background::Background::Color(_) => {
+                        pass::background::Background::Gradient {
+                            top: [0.3, 0.5, 0.8, 1.0],
+                            bottom: [0.8, 0.7, 0.6, 1.0],
+                        }
+                    }
+                    pass::background::Background::Gradient { .. } => Default::default(),
+                };
+                self.background_pass.set_background(background, &self.queue);
+            }
+            // Cycle through the depth formats the device supports
+            VirtualKeyCode::Z => {
+                let mut depth_format = self.depth_format.next();
+                while !self
+                    .device
+                    .features()
+                    .contains(depth_format.required_features())
+                {
+                    depth_format = depth_format.next();
+                }
+                self.set_depth_format(depth_format);
+                log::info!(
+                    "Depth format: {:?} (stencil: {})",
+                    depth_format,
+                    depth_format.has_stencil()
+                );
+            }
+            // Fog off, light fog, thick fog near the ground
+            VirtualKeyCode::F => {
+                let thick_fog = pass::fog::Fog {
+                    color: [0.8, 0.75, 0.7],
+                    density: 0.12,
+                    height: 1.0,
+                    height_falloff: 1.0,
+                };
+                let fog = match self.fog_pass.as_ref().map(pass::fog::FogPass::fog) {
+                    None => Some(pass::fog::Fog::default()),
+                    Some(fog) if fog == pass::fog::Fog::default() => Some(thick_fog),
+                    Some(_) => None,
+                };
+                self.set_fog(fog);
+                log::info!("Fog: {:?}", fog);
+            }
+            // Cycle through the ways the models blend with the background
+            VirtualKeyCode::B => {
+                self.set_blend_mode(self.phong_config.blend_mode.next());
+                log::info!("Blend mode: {:?}", self.phong_config.blend_mode);
+            }
+            // Log where the GPU has the instances
+            VirtualKeyCode::K => self.log_instance_positions(),
+            // Tint the instances in a rainbow, or back to white
+            VirtualKeyCode::R => {
+                let colors = self
+                    .instance_colors
+                    .is_none()
+                    .then(|| rainbow_colors(self.instances.len()));
+                self.set_instance_colors(colors);
+            }
+            // Cycle through the shadow map sizes
+            VirtualKeyCode::O => {
+                let quality = self.shadow_quality.next();
+                match self.set_shadow_quality(quality) {
+                    Ok(()) => log::info!("Shadow quality: {:?}", quality),
+                    Err(e) => log::warn!("Couldn't change the shadow quality: {}", e),
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Updates and renders a single frame
+    fn redraw(&mut self, control_flow: &mut ControlFlow) {
+        self.update();
+        match self.render() {
+            Ok(_) => {}
+            // Reconfigure the surface if it's lost or outdated
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => self.resize(self.size),
+            // The system is out of memory, we should probably quit
+            Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+
+            Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+        }
+    }
+
     fn update(&mut self) {
+        let dt = self.frame_clock.tick();
+
+        self.apply_pending_resize();
+
+        // Trade resolution for speed if frames are taking too long
+        if self.resolution_scaler.update(dt * 1000.0) {
+            log::info!(
+                "Render scale: {:.0}%",
+                self.resolution_scaler.render_scale * 100.0
+            );
+            self.resize_render_targets();
+        }
+
+        // Turn the scene's pivot, then move everything under it along
+        if let Some(pivot) = self
+            .scene
+            .nodes
+            .iter()
+            .position(|node| node.name == SCENE_SPIN_NODE)
+        {
+            let node = &mut self.scene.nodes[pivot];
+            node.set_rotation(
+                cgmath::Quaternion::from_angle_y(cgmath::Deg(SCENE_SPIN_SPEED * dt))
+                    * node.rotation(),
+            );
+        }
+        let scene_instance_data = self
+            .scene
+            .compute_world_transforms()
+            .into_iter()
+            .map(InstanceRaw::from_matrix)
+            .collect::<Vec<_>>();
+        self.queue.write_buffer(
+            &self.scene_instance_buffer,
+            0,
+            bytemuck::cast_slice(&scene_instance_data),
+        );
+
+        // Turn the picked instance so it's easy to spot
+        if let Some(index) = self.selected {
+            let instance = self.instance_mut(index);
+            instance.rotation =
+                cgmath::Quaternion::from_angle_y(cgmath::Deg(SELECTED_SPIN_SPEED * dt))
+                    * instance.rotation;
+        }
+        // Move any instances attached to springs
+        if !self.spring_simulator.is_empty() {
+            self.spring_simulator.update(dt, &mut self.instances);
+            self.mark_instances_dirty(0..self.instances.len());
+        }
+        // Send anything that moved this frame to the GPU
+        self.upload_dirty_instances();
+        self.tick_color_overrides();
+
+        // Ease the clear color toward the mouse so it doesn't jump around
+        if let Some(clear_color) = &mut self.clear_color {
+            let target = self.clear_color_target;
+            let lerp = |from: f64, to: f64| from + (to - from) * CLEAR_COLOR_SMOOTHING;
+            clear_color.r = lerp(clear_color.r, target.r);
+            clear_color.g = lerp(clear_color.g, target.g);
+            clear_color.b = lerp(clear_color.b, target.b);
+            clear_color.a = lerp(clear_color.a, target.a);
+        }
+
         // Sync local app state with camera
-        self.camera_controller.update_camera(&mut self.camera);
+        self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniform.update_view_proj(&self.camera);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+        self.update_lod_level();
+
+        // Update the lights
+        let rotation =
+            cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0));
+        for light in &mut self.light_uniforms {
+            let old_position: cgmath::Vector3<_> = light.position.into();
+            light.position = (rotation * old_position).into();
+            // Keeps directional lights pointing away from where they're drawn
+            let old_direction: cgmath::Vector3<_> = light.direction.into();
+            light.direction = (rotation * old_direction).into();
+        }
+        self.write_lights();
+
+        // The first directional and the first spot light cast shadows
+        for (map, light_type) in [
+            (DIRECTIONAL_SHADOW_MAP, LightType::Directional),
+            (SPOT_SHADOW_MAP, LightType::Spot),
+        ] {
+            let shadow_config = self.shadow_pass.config(map);
+            let aspect = shadow_config.width as f32 / shadow_config.height as f32;
+            let shadow_light = self
+                .light_uniforms
+                .iter()
+                .enumerate()
+                .filter(|(_, light)| light.light_type == light_type as u32)
+                .find_map(|(index, light)| Some((index as u32, light.shadow_view_proj(aspect)?)));
+            self.shadow_pass.set_light(&self.queue, map, shadow_light);
+        }
 
-        // Update the light
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
-        self.queue.write_buffer(
-            &self.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.light_uniform]),
+        // Decals rebuild world positions from depth, so they need every view's camera
+        if !self.decal_pass.is_empty() {
+            let rects = self.viewport_config.rects(
+                self.scene_texture.size.width,
+                self.scene_texture.size.height,
+            );
+            let view_projs = iter::once(self.camera.build_view_projection_matrix()).chain(
+                self.secondary_views()
+                    .iter()
+                    .map(|view_camera| view_camera.camera.build_view_projection_matrix()),
+            );
+            let views = view_projs.zip(rects).collect::<Vec<_>>();
+            self.decal_pass.set_views(&self.queue, &views);
+        }
+
+        // Fog measures the distance to each pixel from the camera of its view
+        if let Some(fog_pass) = &self.fog_pass {
+            let rects = self.viewport_config.rects(
+                self.scene_texture.size.width,
+                self.scene_texture.size.height,
+            );
+            let cameras = iter::once(&self.camera).chain(
+                self.secondary_views()
+                    .iter()
+                    .map(|view_camera| &view_camera.camera),
+            );
+            let views = cameras
+                .zip(rects)
+                .map(|(camera, rect)| (camera.eye, camera.build_view_projection_matrix(), rect))
+                .collect::<Vec<_>>();
+            fog_pass.set_views(&self.queue, &views);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_textures();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.poll_pick();
+
+        // On the web the screenshot buffer gets mapped in the background
+        self.save_pending_screenshot();
+    }
+
+    // Every instance uses the same detail level, picked by how close the nearest one is to
+    // the main camera. The other views are far away by design, so they don't count.
+    fn update_lod_level(&mut self) {
+        let eye = self.camera.eye.to_vec();
+        let nearest = self
+            .instances
+            .iter()
+            .map(|instance| instance.position.distance(eye))
+            .fold(f32::INFINITY, f32::min);
+        let lod_level = LOD_DISTANCES
+            .iter()
+            .filter(|&&distance| nearest > distance)
+            .count()
+            .min(self.lods.len());
+        if lod_level != self.lod_level {
+            log::info!("LOD level: {}", lod_level);
+            self.lod_level = lod_level;
+        }
+    }
+
+    // The meshes to draw at the current `lod_level`
+    fn lod_model(&self) -> &model::Model {
+        match self.lod_level {
+            0 => &self.obj_model,
+            level => &self.lods[level - 1],
+        }
+    }
+
+    // Re-upload any model textures that changed on disk
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_textures(&mut self) {
+        let changed_files = match &self.hot_reloader {
+            Some(hot_reloader) => hot_reloader.changed_files(),
+            None => return,
+        };
+        if changed_files.is_empty() {
+            return;
+        }
+
+        // Edited through the cache so the next load of the model gets the new textures too
+        let edited = resources::model_cache().lock().unwrap().edit(
+            MODEL_FILE,
+            &mut self.obj_model,
+            |obj_model| {
+                for material in obj_model.materials.iter_mut() {
+                    let path = resources::asset_path(&material.diffuse_path);
+                    let is_changed = path
+                        .canonicalize()
+                        .map(|path| changed_files.contains(&path))
+                        .unwrap_or(false);
+                    if !is_changed {
+                        continue;
+                    }
+
+                    log::warn!("Reloading texture {}", material.diffuse_path);
+                    match material.diffuse_texture.reload_from_path(
+                        &path.to_string_lossy(),
+                        &self.device,
+                        &self.queue,
+                    ) {
+                        // The texture was recreated, so the old bind group points to a dead view
+                        Ok(true) => material
+                            .update_bind_group(&self.device, &self.texture_bind_group_layout),
+                        Ok(false) => {}
+                        Err(e) => log::warn!("Couldn't reload {}: {}", material.diffuse_path, e),
+                    }
+                }
+            },
         );
+        if !edited {
+            log::warn!("Can't reload textures while {} is shared", MODEL_FILE);
+        }
     }
 
     // Primary render flow
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let output = match &self.surface {
+            Some(surface) => surface.get_current_texture()?,
+            None => return Ok(()),
+        };
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -699,68 +2788,555 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        if let Some(transparent_pass) = &self.transparent_pass {
+            transparent_pass.sort(&mut encoder);
+        }
+        self.draw_scene(&mut encoder, &self.scene_texture.view);
+        self.tonemap_pass.draw(&mut encoder, &view);
+
+        self.queue.submit(iter::once(encoder.finish()));
+        output.present();
+
+        Ok(())
+    }
+
+    // Records the scene into `view` - shared by the window and screenshots
+    fn draw_scene(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        // The rects are in scene texture pixels, which is smaller than the window when scaled down
+        let rects = self.viewport_config.rects(
+            self.scene_texture.size.width,
+            self.scene_texture.size.height,
+        );
+        let camera_bind_groups = || {
+            iter::once(&self.camera_bind_group).chain(
+                self.secondary_views()
+                    .iter()
+                    .map(|view_camera| &view_camera.bind_group),
+            )
+        };
+
+        // Shadows don't depend on the view, so they only need drawing once
+        self.shadow_pass.draw_shadow_maps(
+            encoder,
+            self.lod_model(),
+            &self.instance_buffer,
+            0..self.instances.len() as u32,
+        );
+
+        // Fill the depth buffer first so the lighting only runs on visible pixels
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    // Later passes can only test against the stencil if it starts out cleared
+                    stencil_ops: self.depth_format.has_stencil().then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: true,
+                    }),
+                }),
+            });
+            for (rect, camera_bind_group) in rects.iter().zip(camera_bind_groups()) {
+                if rect.is_empty() {
+                    continue;
+                }
+                Self::set_viewport(&mut render_pass, rect);
+                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                self.depth_prepass.draw(
+                    &mut render_pass,
+                    self.lod_model(),
+                    0..self.instances.len() as u32,
+                    camera_bind_group,
+                );
+                render_pass.set_vertex_buffer(1, self.showcase.instance_buffer.slice(..));
+                for (mesh, instances) in self.showcase.draws() {
+                    self.depth_prepass.draw_mesh(
+                        &mut render_pass,
+                        mesh,
+                        instances,
+                        camera_bind_group,
+                    );
+                }
+                render_pass.set_vertex_buffer(1, self.scene_instance_buffer.slice(..));
+                for (mesh, instances) in self.scene.draws() {
+                    self.depth_prepass.draw_mesh(
+                        &mut render_pass,
+                        mesh,
+                        instances,
+                        camera_bind_group,
+                    );
+                }
+            }
+        }
+
+        // View space normals, tested against the prepass depth
+        if let Some(normal_pass) = &self.normal_pass {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Normal Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: &normal_pass.texture().view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        // Set the clear color during redraw
-                        // This is basically a background color applied if an object isn't taking up space
-
-                        // This sets it a color that changes based on mouse move
-                        // load: wgpu::LoadOp::Clear(self.clear_color),
-
-                        // A standard clear color
+                        // Anything not covered by geometry reads as facing the camera
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
+                            r: 0.5,
+                            g: 0.5,
+                            b: 1.0,
+                            a: 0.0,
                         }),
                         store: true,
                     },
                 })],
-                // Create a depth stencil buffer using the depth texture
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Load,
                         store: true,
                     }),
                     stencil_ops: None,
                 }),
             });
+            for (rect, camera_bind_group) in rects.iter().zip(camera_bind_groups()) {
+                if rect.is_empty() {
+                    continue;
+                }
+                Self::set_viewport(&mut render_pass, rect);
+                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                normal_pass.draw(
+                    &mut render_pass,
+                    self.lod_model(),
+                    0..self.instances.len() as u32,
+                    camera_bind_group,
+                );
+                render_pass.set_vertex_buffer(1, self.showcase.instance_buffer.slice(..));
+                for (mesh, instances) in self.showcase.draws() {
+                    normal_pass.draw_mesh(&mut render_pass, mesh, instances, camera_bind_group);
+                }
+                render_pass.set_vertex_buffer(1, self.scene_instance_buffer.slice(..));
+                for (mesh, instances) in self.scene.draws() {
+                    normal_pass.draw_mesh(&mut render_pass, mesh, instances, camera_bind_group);
+                }
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Set the clear color during redraw
+                    // This is basically a background color applied if an object isn't taking up space
+
+                    // Either a color that changes based on mouse move, or the background
+                    // color. Gradients get drawn over this in each viewport.
+                    load: wgpu::LoadOp::Clear(
+                        self.clear_color
+                            .unwrap_or_else(|| self.background_pass.clear_color()),
+                    ),
+                    store: true,
+                },
+            })],
+            // Create a depth stencil buffer using the depth texture
+            // (keeping what the prepass wrote)
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        // Every view draws into the same render pass, just with a different viewport and camera
+        for (rect, camera_bind_group) in rects.iter().zip(camera_bind_groups()) {
+            self.draw_to_viewport(&mut render_pass, rect, camera_bind_group);
+        }
 
-            // Setup our render pipeline with our config earlier in `new()`
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        // Outline the view that's receiving input
+        if rects.len() > 1 {
+            if let Some(rect) = rects.get(self.viewport_config.active) {
+                if !rect.is_empty() {
+                    Self::set_viewport(&mut render_pass, rect);
+                    self.viewport_outline.draw(&mut render_pass);
+                }
+            }
+        }
+        drop(render_pass);
+
+        // Decals read the finished depth buffer, so they get a pass of their own
+        self.decal_pass.draw(encoder, view, &rects);
+        // Last, so decals get fogged too
+        if let Some(fog_pass) = &self.fog_pass {
+            fog_pass.draw(encoder, view, &rects);
+        }
+    }
 
-            // Setup lighting pipeline
-            render_pass.set_pipeline(&self.light_render_pipeline);
-            // Draw/calculate the lighting on models
-            render_pass.draw_light_model(
-                &self.obj_model,
-                &self.camera_bind_group,
+    // Draws the scene confined to one part of the screen
+    fn draw_to_viewport<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        rect: &viewport::ViewportRect,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if rect.is_empty() {
+            return;
+        }
+        Self::set_viewport(render_pass, rect);
+        self.background_pass.draw(render_pass);
+
+        // Setup lighting pipeline
+        render_pass.set_pipeline(&self.light_render_pipeline);
+        // Draw/calculate the lighting on models
+        render_pass.draw_light_mesh_instanced(
+            &self.light_marker,
+            0..self.light_uniforms.len() as u32,
+            camera_bind_group,
+            &self.light_bind_group,
+        );
+
+        // Setup render pipeline
+        // Draw the models. The LODs don't have materials of their own, they go mesh by mesh
+        // with `obj_model`'s
+        let instances = 0..self.instances.len() as u32;
+        match &self.vertex_pull_pipeline {
+            Some(pipeline) => {
+                render_pass.set_pipeline(pipeline);
+                // The pull pipeline has no mesh vertex buffer, so instances move up to slot 0
+                // and their colors to slot 1
+                render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instance_color_buffer.slice(..));
+                if self.lod_level == 0 {
+                    render_pass.draw_model_instanced_pulled(
+                        &self.obj_model,
+                        instances,
+                        camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                } else {
+                    for mesh in &self.lod_model().meshes {
+                        render_pass.draw_mesh_instanced_pulled(
+                            mesh,
+                            &self.obj_model.materials[mesh.material],
+                            instances.clone(),
+                            camera_bind_group,
+                            &self.light_bind_group,
+                        );
+                    }
+                }
+            }
+            None => {
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                render_pass.set_vertex_buffer(2, self.instance_color_buffer.slice(..));
+                if self.lod_level == 0 {
+                    render_pass.draw_model_instanced(
+                        &self.obj_model,
+                        instances,
+                        camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                } else {
+                    for mesh in &self.lod_model().meshes {
+                        render_pass.draw_mesh_instanced(
+                            mesh,
+                            &self.obj_model.materials[mesh.material],
+                            instances.clone(),
+                            camera_bind_group,
+                            &self.light_bind_group,
+                        );
+                    }
+                }
+            }
+        }
+
+        // The primitives don't have vertex pull bind groups, so they always use the regular pipeline
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(1, self.showcase.instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.showcase.instance_color_buffer.slice(..));
+        for (mesh, instances) in self.showcase.draws() {
+            render_pass.draw_mesh_instanced(
+                mesh,
+                &self.showcase.model.materials[mesh.material],
+                instances,
+                camera_bind_group,
                 &self.light_bind_group,
             );
+        }
+        render_pass.set_vertex_buffer(1, self.scene_instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.scene_instance_color_buffer.slice(..));
+        for (mesh, instances) in self.scene.draws() {
+            render_pass.draw_mesh_instanced(
+                mesh,
+                &self.scene.model.materials[mesh.material],
+                instances,
+                camera_bind_group,
+                &self.light_bind_group,
+            );
+        }
 
-            // Setup render pipeline
-            render_pass.set_pipeline(&self.render_pipeline);
-            // Draw the models
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
+        // After everything opaque so it has something to blend over. The instances were sorted
+        // for the main camera, the other views use the same order.
+        if let Some(transparent_pass) = &self.transparent_pass {
+            transparent_pass.draw(
+                render_pass,
+                &self.transparent_pipeline,
+                camera_bind_group,
                 &self.light_bind_group,
             );
         }
 
+        if let Some(index) = self.selected {
+            let index = index as u32;
+            self.outline_pass.draw(
+                render_pass,
+                &self.instance_buffer,
+                index..index + 1,
+                camera_bind_group,
+            );
+        }
+
+        // After the models so the depth test can hide gizmos behind them
+        if let Some(light_gizmo_pass) = &self.light_gizmo_pass {
+            light_gizmo_pass.draw(
+                render_pass,
+                camera_bind_group,
+                &self.light_bind_group,
+                self.light_uniforms.len() as u32,
+            );
+        }
+        if let Some(light_direction_pass) = &self.light_direction_pass {
+            light_direction_pass.draw(render_pass, camera_bind_group);
+        }
+    }
+
+    fn set_viewport(render_pass: &mut wgpu::RenderPass, rect: &viewport::ViewportRect) {
+        render_pass.set_viewport(
+            rect.x as f32,
+            rect.y as f32,
+            rect.width as f32,
+            rect.height as f32,
+            0.0,
+            1.0,
+        );
+        // The viewport already clips, but the scissor makes sure nothing bleeds into the next view
+        render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+    }
+
+    // Reads the instances back from the GPU, e.g. to save where a GPU simulation left them.
+    // This stalls until the GPU is done, so use `readback_instances_async` during a frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn readback_instances(&self) -> anyhow::Result<Vec<Instance>> {
+        let readback = self.readback_instances_async();
+        self.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(readback)
+    }
+
+    // Resolves once the GPU has copied the instances over. On native that only happens
+    // when something calls `device.poll`, the browser takes care of it on the web.
+    pub fn readback_instances_async(
+        &self,
+    ) -> impl std::future::Future<Output = anyhow::Result<Vec<Instance>>> {
+        let size =
+            (self.instances.len() * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Instance Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(&self.instance_buffer, 0, &staging_buffer, 0, size);
         self.queue.submit(iter::once(encoder.finish()));
-        output.present();
 
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+
+        async move {
+            receiver.await??;
+            let instances = {
+                let data = staging_buffer.slice(..).get_mapped_range();
+                bytemuck::cast_slice::<u8, InstanceRaw>(&data)
+                    .iter()
+                    .map(Instance::from_raw)
+                    .collect()
+            };
+            staging_buffer.unmap();
+            Ok(instances)
+        }
+    }
+
+    fn log_instance_positions(&self) {
+        let log_instances = |instances: anyhow::Result<Vec<Instance>>| match instances {
+            Ok(instances) => {
+                for (i, instance) in instances.iter().enumerate() {
+                    log::info!("Instance {}: {:?}", i, instance.position);
+                }
+            }
+            Err(e) => log::warn!("Couldn't read back the instances: {}", e),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        log_instances(self.readback_instances());
+        #[cfg(target_arch = "wasm32")]
+        {
+            let readback = self.readback_instances_async();
+            wasm_bindgen_futures::spawn_local(async move { log_instances(readback.await) });
+        }
+    }
+
+    // Starts looking for the instance under a window pixel, `poll_pick` handles what's found.
+    // Native only for now, the web build leaves clicks alone.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn pick(&mut self, x: u32, y: u32) {
+        // Same layout as the screen, but in window pixels rather than scene texture pixels
+        let rects = self
+            .viewport_config
+            .rects(self.config.width, self.config.height);
+        let camera_bind_groups = iter::once(&self.camera_bind_group).chain(
+            self.secondary_views()
+                .iter()
+                .map(|view_camera| &view_camera.bind_group),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Pick Encoder"),
+            });
+        self.pick_pass.draw(
+            &mut encoder,
+            &self.obj_model,
+            &self.instance_buffer,
+            0..self.instances.len() as u32,
+            rects.iter().zip(camera_bind_groups),
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        if !self
+            .pick_pass
+            .request_pixel(x, y, &self.device, &self.queue)
+        {
+            log::warn!("Can't pick at ({}, {}) right now", x, y);
+        }
+    }
+
+    // Reacts to the last `pick` once the GPU has the pixel, without waiting on it
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll_pick(&mut self) {
+        match self.pick_pass.poll_pixel(&self.device) {
+            // Still waiting
+            None => {}
+            Some(Some(index)) => {
+                log::warn!("Picked instance {}", index);
+                // Half a second at 60fps
+                self.flash_color(index, [1.0, 0.8, 0.2, 1.0], 30);
+                self.selected = Some(index);
+            }
+            Some(None) => {
+                log::warn!("Nothing picked");
+                self.selected = None;
+            }
+        }
+    }
+
+    // Renders an extra frame offscreen and saves it as a PNG (or downloads it on the web).
+    // A timestamp is added to `path` so previous screenshots aren't overwritten.
+    pub fn take_screenshot(&mut self, path: &str) -> anyhow::Result<()> {
+        let screenshot = self.capture_frame(&screenshot::timestamped_path(path))?;
+
+        // Native can wait for the GPU right here, the web finishes it up in `update`
+        #[cfg(not(target_arch = "wasm32"))]
+        self.device.poll(wgpu::Maintain::Wait);
+
+        self.pending_screenshot = Some(screenshot);
+        self.save_pending_screenshot();
         Ok(())
     }
+
+    // Renders a frame and returns the pixels (tightly packed RGBA8), blocking until the GPU is done
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_headless(&mut self) -> anyhow::Result<Vec<u8>> {
+        let screenshot = self.capture_frame("")?;
+        self.device.poll(wgpu::Maintain::Wait);
+        screenshot.try_read().unwrap_or_else(|| {
+            Err(anyhow::anyhow!(
+                "Frame wasn't ready after waiting on the GPU"
+            ))
+        })
+    }
+
+    // Draws a frame into an offscreen texture and starts copying it back to the CPU
+    fn capture_frame(&mut self, path: &str) -> anyhow::Result<screenshot::Screenshot> {
+        let size = wgpu::Extent3d {
+            width: self.config.width,
+            height: self.config.height,
+            depth_or_array_layers: 1,
+        };
+        // We can't read back from the swapchain, so we render into our own texture.
+        // It uses the surface format so we can reuse the same pipelines.
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Screenshot Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screenshot Encoder"),
+            });
+        // Same as a regular frame, so the screenshot is at the current render scale too
+        if let Some(transparent_pass) = &self.transparent_pass {
+            transparent_pass.sort(&mut encoder);
+        }
+        self.draw_scene(&mut encoder, &self.scene_texture.view);
+        self.tonemap_pass.draw(&mut encoder, &view);
+        let mut screenshot = screenshot::Screenshot::new(
+            &self.device,
+            &mut encoder,
+            &texture,
+            self.config.format,
+            size.width,
+            size.height,
+            path,
+        )?;
+        self.queue.submit(iter::once(encoder.finish()));
+        screenshot.map();
+        Ok(screenshot)
+    }
+
+    fn save_pending_screenshot(&mut self) {
+        let result = match &self.pending_screenshot {
+            Some(screenshot) => screenshot.try_save(),
+            None => return,
+        };
+        match result {
+            // Still waiting on the GPU
+            None => return,
+            Some(Ok(path)) => log::warn!("Saved screenshot to {}", path),
+            Some(Err(e)) => log::warn!("Couldn't save screenshot: {}", e),
+        }
+        self.pending_screenshot = None;
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
@@ -800,7 +3376,7 @@ pub async fn run() {
     }
 
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
+    let mut state = State::new(Some(&window), window.inner_size()).await;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -833,26 +3409,68 @@ pub async fn run() {
                     }
                 }
             }
+            #[cfg(not(target_arch = "wasm32"))]
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                state.update();
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
-                    }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
-                }
+                state.redraw(control_flow);
             }
+            #[cfg(not(target_arch = "wasm32"))]
             Event::RedrawEventsCleared => {
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
                 window.request_redraw();
             }
+            // On the web winit already drives the loop from `requestAnimationFrame`,
+            // so we draw once per browser frame instead of queueing extra redraws
+            #[cfg(target_arch = "wasm32")]
+            Event::MainEventsCleared => {
+                state.redraw(control_flow);
+            }
             _ => {}
         }
     });
 }
+
+// Renders a single frame without opening a window, e.g. for thumbnails or CI.
+// Returns `width * height` RGBA8 pixels (sRGB encoded).
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn render_headless(width: u32, height: u32) -> anyhow::Result<Vec<u8>> {
+    let mut state = State::new(None, winit::dpi::PhysicalSize::new(width, height)).await;
+    state.update();
+    state.render_headless()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_smoothing_reaches_target() {
+        let mut camera = Camera {
+            projection: Projection::Perspective,
+            eye: (0.0, 0.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut controller = CameraController::new(0.2);
+        controller.target_eye = Some((0.0, 5.0, 5.0).into());
+        controller.target_fovy = Some(30.0);
+
+        // One second at 60fps, `smooth_factor` leaves 0.8^60 of the distance
+        let dt = 1.0 / 60.0;
+        for _ in 0..60 {
+            controller.update_camera(&mut camera, dt);
+        }
+
+        let target_eye = cgmath::Point3::new(0.0, 5.0, 5.0);
+        assert!(
+            (camera.eye - target_eye).magnitude() < 1e-3,
+            "{:?}",
+            camera.eye
+        );
+        assert!((camera.fovy - 30.0).abs() < 1e-3, "{}", camera.fovy);
+    }
+}
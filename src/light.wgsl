@@ -9,10 +9,22 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
+    // 0 is a point light, 1 is directional, 2 is a spot light
+    light_type: u32,
     color: vec3<f32>,
+    direction: vec3<f32>,
+    // Cosines of the spot light cone angles
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+}
+// Sized by `PhongConfig::max_lights`. On the web this becomes a fixed size uniform array,
+// see `pipeline::lights_shader_source`
+struct Lights {
+    count: u32,
+    lights: array<Light>,
 }
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -26,7 +38,10 @@ struct VertexOutput {
 @vertex
 fn vs_main(
     model: VertexInput,
+    // One instance per light
+    @builtin(instance_index) instance_index: u32,
 ) -> VertexOutput {
+    let light = lights.lights[instance_index];
     let scale = 0.25;
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
@@ -1,5 +1,12 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::ops::Range;
 
+use cgmath::{InnerSpace, SquareMatrix, Zero};
+
+use wgpu::util::DeviceExt;
+
+use crate::instance::InstanceRaw;
 use crate::texture;
 
 pub trait Vertex {
@@ -12,6 +19,13 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    // Second UV set for lightmaps, these shouldn't overlap or tile
+    pub tex_coords2: [f32; 2],
+    // (1,0,0), (0,1,0) or (0,0,1) for the corners of each triangle, used to draw wireframes.
+    // Only meaningful after `primitives::add_barycentric_coordinates`.
+    pub barycentric: [f32; 3],
+    // Points along +U on the surface, for normal mapping. Filled in by `compute_tangents`.
+    pub tangent: [f32; 3],
 }
 
 impl Vertex for ModelVertex {
@@ -36,23 +50,440 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                // After the instance attributes (5 to 12)
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 13]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
 
+// Describes one attribute inside a `DynamicVertex`
+#[derive(Copy, Clone, Debug)]
+pub struct VertexAttributeDesc {
+    // Offset from the start of the vertex, in floats
+    pub offset: u32,
+    // Number of floats in the attribute (1 to 4)
+    pub size: u32,
+    pub shader_location: u32,
+}
+
+// Vertex data with a layout decided at runtime, for experimenting with custom shader inputs
+// (ambient occlusion, extra UV sets, etc) without having to change `ModelVertex`.
+// `data` holds all the vertices interleaved, each one `stride()` floats long.
+pub struct DynamicVertex {
+    pub data: Vec<f32>,
+    pub layout: Vec<VertexAttributeDesc>,
+    attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl DynamicVertex {
+    pub fn new(layout: Vec<VertexAttributeDesc>, data: Vec<f32>) -> Self {
+        let attributes = layout
+            .iter()
+            .map(|attribute| wgpu::VertexAttribute {
+                offset: (attribute.offset as usize * std::mem::size_of::<f32>())
+                    as wgpu::BufferAddress,
+                shader_location: attribute.shader_location,
+                format: match attribute.size {
+                    1 => wgpu::VertexFormat::Float32,
+                    2 => wgpu::VertexFormat::Float32x2,
+                    3 => wgpu::VertexFormat::Float32x3,
+                    4 => wgpu::VertexFormat::Float32x4,
+                    size => panic!("Vertex attributes can only have 1-4 floats, got {}", size),
+                },
+            })
+            .collect();
+
+        Self {
+            data,
+            layout,
+            attributes,
+        }
+    }
+
+    // Number of floats per vertex
+    pub fn stride(&self) -> usize {
+        self.layout
+            .iter()
+            .map(|attribute| (attribute.offset + attribute.size) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        match self.stride() {
+            0 => 0,
+            stride => self.data.len() / stride,
+        }
+    }
+
+    // Same as `Vertex::desc()`, but built from the runtime layout
+    pub fn desc(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: (self.stride() * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+// A mesh using `DynamicVertex` data. This won't work with the main render pipeline
+// (which expects `ModelVertex`), use it with a custom pipeline built from `vertices.desc()`.
+pub struct DynamicPrimitiveMesh {
+    pub vertices: DynamicVertex,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+}
+
+impl DynamicPrimitiveMesh {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: Vec<VertexAttributeDesc>,
+        vertex_data: Vec<f32>,
+        index_data: &[u32],
+    ) -> Self {
+        let vertices = DynamicVertex::new(layout, vertex_data);
+        // Out of range indices only show up as garbage on screen, if at all
+        debug_assert!(
+            index_data
+                .iter()
+                .all(|&index| (index as usize) < vertices.len()),
+            "Index out of range for {} dynamic vertices",
+            vertices.len()
+        );
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Dynamic Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices.data),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Dynamic Index Buffer"),
+            contents: bytemuck::cast_slice(index_data),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            vertices,
+            vertex_buffer,
+            index_buffer,
+            num_elements: index_data.len() as u32,
+        }
+    }
+}
+
+// Laid out to match the `MaterialUniform` in `shader.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    atlas_rect: texture::AtlasRect,
+    // Higher is a smaller, sharper highlight
+    shininess: f32,
+    // Only used by the PBR shading, see `Material::set_pbr`
+    metallic: f32,
+    roughness: f32,
+    ao: f32,
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
+    // File the diffuse texture was loaded from (relative to the assets folder)
+    pub diffuse_path: String,
+    // Baked lighting, sampled with `tex_coords2`. When this is `None` we bind a white texture instead.
+    pub lightmap_texture: Option<texture::Texture>,
+    fallback_lightmap: texture::Texture,
+    // Tangent space normals. When this is `None` we bind a flat (0, 0, 1) normal instead.
+    pub normal_texture: Option<texture::Texture>,
+    fallback_normal: texture::Texture,
+    // How much of the specular highlight shows, from the red channel.
+    // When this is `None` we bind a white texture instead, so it's all there.
+    pub specular_texture: Option<texture::Texture>,
+    fallback_specular: texture::Texture,
+    // Set when the diffuse texture comes from a `TextureAtlas` instead, see `use_atlas`
+    pub atlas_rect: Option<texture::AtlasRect>,
+    uniform: MaterialUniform,
+    uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        diffuse_texture: texture::Texture,
+        diffuse_path: &str,
+        lightmap_texture: Option<texture::Texture>,
+        normal_texture: Option<texture::Texture>,
+        specular_texture: Option<texture::Texture>,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let fallback_lightmap =
+            texture::Texture::from_color(device, queue, [255, 255, 255, 255], "Fallback Lightmap");
+        let fallback_normal = texture::Texture::from_color_linear(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            "Fallback Normal Map",
+        );
+        let fallback_specular = texture::Texture::from_color_linear(
+            device,
+            queue,
+            [255, 255, 255, 255],
+            "Fallback Specular Map",
+        );
+        let uniform = MaterialUniform {
+            atlas_rect: texture::AtlasRect::FULL,
+            shininess: 32.0,
+            metallic: 0.0,
+            roughness: 0.5,
+            ao: 1.0,
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &diffuse_texture,
+            lightmap_texture.as_ref().unwrap_or(&fallback_lightmap),
+            normal_texture.as_ref().unwrap_or(&fallback_normal),
+            specular_texture.as_ref().unwrap_or(&fallback_specular),
+            &uniform_buffer,
+            layout,
+        );
+
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            diffuse_path: diffuse_path.to_string(),
+            lightmap_texture,
+            fallback_lightmap,
+            normal_texture,
+            fallback_normal,
+            specular_texture,
+            fallback_specular,
+            atlas_rect: None,
+            uniform,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // Cheap enough to change every frame, e.g. to animate it
+    pub fn set_shininess(&mut self, queue: &wgpu::Queue, shininess: f32) {
+        self.uniform.shininess = shininess;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    // Only the PBR shading reads these, the Blinn-Phong one uses `shininess` instead.
+    // Roughness is kept above 0 so the highlight never shrinks to nothing.
+    pub fn set_pbr(&mut self, queue: &wgpu::Queue, metallic: f32, roughness: f32, ao: f32) {
+        self.uniform.metallic = metallic.clamp(0.0, 1.0);
+        self.uniform.roughness = roughness.clamp(0.04, 1.0);
+        self.uniform.ao = ao.clamp(0.0, 1.0);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    // Bind groups hold onto the texture view, so this needs to be called
+    // whenever the texture gets recreated (like a hot reload with a new size)
+    pub fn update_bind_group(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        // The atlas is bound instead of `diffuse_texture`, so there's nothing to update
+        if self.atlas_rect.is_some() {
+            return;
+        }
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.diffuse_texture,
+            self.lightmap_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_lightmap),
+            self.normal_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_normal),
+            self.specular_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_specular),
+            &self.uniform_buffer,
+            layout,
+        );
+    }
+
+    // Sample the diffuse color from part of a shared atlas texture instead of `diffuse_texture`.
+    // Call this again with the same rect if the atlas texture gets recreated.
+    pub fn use_atlas(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        atlas: &texture::Texture,
+        rect: texture::AtlasRect,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.atlas_rect = Some(rect);
+        self.uniform.atlas_rect = rect;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+        self.bind_group = Self::create_bind_group(
+            device,
+            atlas,
+            self.lightmap_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_lightmap),
+            self.normal_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_normal),
+            self.specular_texture
+                .as_ref()
+                .unwrap_or(&self.fallback_specular),
+            &self.uniform_buffer,
+            layout,
+        );
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        diffuse_texture: &texture::Texture,
+        lightmap_texture: &texture::Texture,
+        normal_texture: &texture::Texture,
+        specular_texture: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&lightmap_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&lightmap_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&specular_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&specular_texture.sampler),
+                },
+            ],
+            label: None,
+        })
+    }
+}
+
 pub struct Mesh {
     pub name: String,
+    // CPU copies of what's in the buffers, for processing like `Model::generate_lods`
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // Only set when the vertex pulling path is enabled, see `enable_vertex_pull`
+    pub vertex_pull_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: Vec<ModelVertex>,
+        indices: Vec<u32>,
+        material: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&vertices),
+            // Storage lets the vertex pulling path read the same buffer (WebGL doesn't have it)
+            usage: if cfg!(target_arch = "wasm32") {
+                wgpu::BufferUsages::VERTEX
+            } else {
+                wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE
+            },
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: name.to_string(),
+            num_elements: indices.len() as u32,
+            vertices,
+            indices,
+            vertex_buffer,
+            index_buffer,
+            material,
+            vertex_pull_bind_group: None,
+        }
+    }
+
+    // Binds the vertex buffer as a storage buffer so shaders can read vertices by index.
+    // The buffer needs `STORAGE` usage, which isn't available on WebGL.
+    pub fn enable_vertex_pull(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        self.vertex_pull_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.vertex_buffer.as_entire_binding(),
+            }],
+            label: Some("vertex_pull_bind_group"),
+        }));
+    }
 }
 
 pub struct Model {
@@ -60,30 +491,609 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    // Simplified copies of every mesh, one set per ratio (e.g. `[0.5, 0.25]`).
+    // The result is ordered by ratio, then mesh, and keeps each mesh's material.
+    pub fn generate_lods(&self, ratios: &[f32], device: &wgpu::Device) -> Vec<Mesh> {
+        ratios
+            .iter()
+            .flat_map(|&ratio| {
+                self.meshes.iter().map(move |mesh| {
+                    let (vertices, indices) = simplify_mesh(&mesh.vertices, &mesh.indices, ratio);
+                    Mesh::new(
+                        device,
+                        &format!("{} LOD {}", mesh.name, ratio),
+                        vertices,
+                        indices,
+                        mesh.material,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    // Inverted hull copies of every mesh for drawing outlines, see `outline_mesh`
+    pub fn generate_outline_meshes(&self, thickness: f32, device: &wgpu::Device) -> Vec<Mesh> {
+        self.meshes
+            .iter()
+            .map(|mesh| {
+                let (vertices, indices) = outline_mesh(&mesh.vertices, &mesh.indices, thickness);
+                Mesh::new(
+                    device,
+                    &format!("{} Outline", mesh.name),
+                    vertices,
+                    indices,
+                    mesh.material,
+                )
+            })
+            .collect()
+    }
+}
+
+// One node of a `Scene`. Nodes without meshes are kept too (e.g. a group or a joint),
+// so `parent` always points at the node it was under in the file.
+pub struct Node {
+    pub name: String,
+    // Index into `Scene::nodes`, change it with `Scene::set_parent`
+    parent: Option<usize>,
+    // Relative to the parent, change them with the setters so the node gets marked dirty
+    position: cgmath::Vector3<f32>,
+    rotation: cgmath::Quaternion<f32>,
+    scale: cgmath::Vector3<f32>,
+    // Set when the local transform (or the parent) changed since `Scene::compute_world_transforms`
+    local_transform_dirty: bool,
+    // With every parent's transform applied, where the meshes end up in the world
+    pub world_transform: cgmath::Matrix4<f32>,
+    // Which of `Scene::model`'s meshes this node draws, empty if none
+    pub meshes: Range<usize>,
+}
+
+impl Node {
+    // A root node with no transform, see the setters and `Scene::set_parent`
+    pub fn new(name: String, meshes: Range<usize>) -> Self {
+        Self {
+            name,
+            parent: None,
+            position: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            local_transform_dirty: true,
+            world_transform: cgmath::Matrix4::identity(),
+            meshes,
+        }
+    }
+
+    pub fn rotation(&self) -> cgmath::Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn set_position(&mut self, position: cgmath::Vector3<f32>) {
+        self.position = position;
+        self.local_transform_dirty = true;
+    }
+
+    pub fn set_rotation(&mut self, rotation: cgmath::Quaternion<f32>) {
+        self.rotation = rotation;
+        self.local_transform_dirty = true;
+    }
+
+    pub fn set_scale(&mut self, scale: cgmath::Vector3<f32>) {
+        self.scale = scale;
+        self.local_transform_dirty = true;
+    }
+
+    // Scale, then rotate, then translate, same as glTF
+    pub fn local_transform(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+// A hierarchy of nodes sharing the meshes and materials in `model`, see `load_scene_gltf`
+pub struct Scene {
+    pub model: Model,
+    pub nodes: Vec<Node>,
+}
+
+impl Scene {
+    // Moves `child` under `parent`, keeping its local transform (so it moves with the parent).
+    // Loops aren't checked here, `compute_world_transforms` panics on them.
+    pub fn set_parent(&mut self, child: usize, parent: usize) {
+        self.nodes[child].parent = Some(parent);
+        self.nodes[child].local_transform_dirty = true;
+    }
+
+    // Multiplies each node's local transform by its parent's world transform and returns the
+    // results, in the same order as `nodes`. Only nodes that are dirty, or under one that is,
+    // get recomputed. Panics if a node ends up being its own ancestor.
+    pub fn compute_world_transforms(&mut self) -> Vec<cgmath::Matrix4<f32>> {
+        let order = self.parents_first();
+        // Whether each node's world transform changed this time, children have to follow
+        let mut changed = vec![false; self.nodes.len()];
+        for index in order {
+            let parent = self.nodes[index].parent;
+            let parent_changed = parent.is_some_and(|parent| changed[parent]);
+            if !self.nodes[index].local_transform_dirty && !parent_changed {
+                continue;
+            }
+            let local_transform = self.nodes[index].local_transform();
+            self.nodes[index].world_transform = match parent {
+                Some(parent) => self.nodes[parent].world_transform * local_transform,
+                None => local_transform,
+            };
+            self.nodes[index].local_transform_dirty = false;
+            changed[index] = true;
+        }
+        self.nodes.iter().map(|node| node.world_transform).collect()
+    }
+
+    // Node indices ordered so every parent comes before its children (depth first)
+    fn parents_first(&self) -> Vec<usize> {
+        #[derive(Copy, Clone, PartialEq)]
+        enum Visit {
+            NotYet,
+            InProgress,
+            Done,
+        }
+
+        let mut visits = vec![Visit::NotYet; self.nodes.len()];
+        let mut order = Vec::with_capacity(self.nodes.len());
+        for start in 0..self.nodes.len() {
+            // Walk up to the first ancestor that's already sorted, then add them top down
+            let mut chain = Vec::new();
+            let mut current = Some(start);
+            while let Some(index) = current {
+                match visits[index] {
+                    Visit::Done => break,
+                    Visit::InProgress => panic!(
+                        "Scene node {} ({:?}) is its own ancestor, the parents form a loop",
+                        index, self.nodes[index].name
+                    ),
+                    Visit::NotYet => {
+                        visits[index] = Visit::InProgress;
+                        chain.push(index);
+                        current = self.nodes[index].parent;
+                    }
+                }
+            }
+            for &index in chain.iter().rev() {
+                visits[index] = Visit::Done;
+                order.push(index);
+            }
+        }
+        order
+    }
+
+    // One instance per node, in the same order as `nodes`
+    pub fn instance_data(&self) -> Vec<InstanceRaw> {
+        self.nodes
+            .iter()
+            .map(|node| InstanceRaw::from_matrix(node.world_transform))
+            .collect()
+    }
+
+    // Meshes paired with the instance of the node drawing them
+    pub fn draws(&self) -> impl Iterator<Item = (&Mesh, Range<u32>)> {
+        self.nodes.iter().enumerate().flat_map(|(index, node)| {
+            let instance = index as u32;
+            self.model.meshes[node.meshes.clone()]
+                .iter()
+                .map(move |mesh| (mesh, instance..instance + 1))
+        })
+    }
+}
+
+// Works out which way U runs across each triangle and averages that over the triangles
+// sharing each vertex, made perpendicular to the vertex normal. Needs UVs and normals.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![cgmath::Vector3::zero(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [v0, v1, v2] = [triangle[0], triangle[1], triangle[2]].map(|i| vertices[i as usize]);
+        let edge1 = cgmath::Vector3::from(v1.position) - cgmath::Vector3::from(v0.position);
+        let edge2 = cgmath::Vector3::from(v2.position) - cgmath::Vector3::from(v0.position);
+        let (du1, dv1) = (
+            v1.tex_coords[0] - v0.tex_coords[0],
+            v1.tex_coords[1] - v0.tex_coords[1],
+        );
+        let (du2, dv2) = (
+            v2.tex_coords[0] - v0.tex_coords[0],
+            v2.tex_coords[1] - v0.tex_coords[1],
+        );
+        // UVs squashed into a line (or a point) don't say which way U runs
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * dv2 - edge2 * dv1) / determinant;
+        for &index in triangle {
+            tangents[index as usize] += tangent;
+        }
+    }
+
+    for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+        let normal = cgmath::Vector3::from(vertex.normal);
+        let tangent = tangent - normal * normal.dot(tangent);
+        let tangent = if tangent.magnitude2() > 0.0 {
+            tangent
+        } else {
+            // No usable UVs, so any direction along the surface will do
+            let axis = if normal.x.abs() < 0.9 {
+                cgmath::Vector3::unit_x()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            axis - normal * normal.dot(axis)
+        };
+        vertex.tangent = tangent.normalize().into();
+    }
+}
+
+// Pushes every vertex out by `thickness` and flips the triangles, so drawn with back face
+// culling only the shell sticking out behind the original mesh is visible.
+// Hard edges split a corner into several vertices with different normals, which would tear
+// the hull open. So the offset uses a smooth normal, averaged over every face that touches
+// the same position, no matter what normals the mesh itself has.
+pub fn outline_mesh(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    thickness: f32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    // Exact bit patterns, since split vertices are copies of the same position
+    let key = |position: [f32; 3]| position.map(f32::to_bits);
+
+    let mut smooth_normals: HashMap<[u32; 3], cgmath::Vector3<f32>> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let [p0, p1, p2] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].position);
+        // Not normalized, so bigger faces count for more
+        let normal = face_normal(p0, p1, p2);
+        for position in [p0, p1, p2] {
+            *smooth_normals
+                .entry(key(position))
+                .or_insert_with(cgmath::Vector3::zero) += normal;
+        }
+    }
+
+    let hull = vertices
+        .iter()
+        .map(|vertex| {
+            let normal = smooth_normals
+                .get(&key(vertex.position))
+                .filter(|normal| normal.magnitude2() > 0.0)
+                .map(|normal| normal.normalize())
+                .unwrap_or_else(|| cgmath::Vector3::from(vertex.normal));
+            let position = cgmath::Vector3::from(vertex.position) + normal * thickness;
+            ModelVertex {
+                position: position.into(),
+                // Faces point inward now
+                normal: (-normal).into(),
+                ..*vertex
+            }
+        })
+        .collect();
+    let flipped = indices
+        .chunks_exact(3)
+        .flat_map(|triangle| [triangle[0], triangle[2], triangle[1]])
+        .collect();
+
+    (hull, flipped)
+}
+
+// Symmetric 4x4 error matrix from Garland & Heckbert's "Surface Simplification Using
+// Quadric Error Metrics", stored as the upper triangle
+#[derive(Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Squared distance to the plane ax + by + cz + d = 0
+    fn from_plane(a: f64, b: f64, c: f64, d: f64) -> Self {
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut sum = *self;
+        for (value, other) in sum.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+        sum
+    }
+
+    fn error(&self, position: [f32; 3]) -> f64 {
+        let [x, y, z] = position.map(|value| value as f64);
+        let q = &self.0;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// A candidate edge collapse, `b` gets merged into `a`
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    // Where along the edge the merged vertex goes (0 = a, 1 = b)
+    t: f32,
+    // If either vertex changed since this was queued, it's out of date
+    stamps: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so `BinaryHeap` pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+fn lerp_vertex(a: &ModelVertex, b: &ModelVertex, t: f32) -> ModelVertex {
+    fn lerp<const N: usize>(a: [f32; N], b: [f32; N], t: f32) -> [f32; N] {
+        let mut out = a;
+        for i in 0..N {
+            out[i] = a[i] + (b[i] - a[i]) * t;
+        }
+        out
+    }
+    let normal = cgmath::Vector3::from(lerp(a.normal, b.normal, t));
+    ModelVertex {
+        position: lerp(a.position, b.position, t),
+        tex_coords: lerp(a.tex_coords, b.tex_coords, t),
+        normal: if normal.magnitude2() > 0.0 {
+            normal.normalize().into()
+        } else {
+            a.normal
+        },
+        tex_coords2: lerp(a.tex_coords2, b.tex_coords2, t),
+        barycentric: lerp(a.barycentric, b.barycentric, t),
+        tangent: lerp(a.tangent, b.tangent, t),
+    }
+}
+
+// Not normalized, we only care about the direction
+fn face_normal(p0: [f32; 3], p1: [f32; 3], p2: [f32; 3]) -> cgmath::Vector3<f32> {
+    let p0 = cgmath::Vector3::from(p0);
+    (cgmath::Vector3::from(p1) - p0).cross(cgmath::Vector3::from(p2) - p0)
+}
+
+fn plan_collapse(
+    vertices: &[ModelVertex],
+    quadrics: &[Quadric],
+    stamps: &[u32],
+    a: usize,
+    b: usize,
+) -> Collapse {
+    let quadric = quadrics[a].add(&quadrics[b]);
+    // Solving for the exact minimum can be unstable on flat areas,
+    // so we just try the two ends and the middle
+    let (cost, t) = [0.0, 0.5, 1.0]
+        .into_iter()
+        .map(|t| {
+            let position = lerp_vertex(&vertices[a], &vertices[b], t).position;
+            (quadric.error(position), t)
+        })
+        .min_by(|(cost_a, _), (cost_b, _)| cost_a.total_cmp(cost_b))
+        .unwrap();
+    Collapse {
+        cost,
+        a,
+        b,
+        t,
+        stamps: (stamps[a], stamps[b]),
+    }
+}
+
+// Lowers the triangle count to roughly `target_ratio` of the original by repeatedly
+// collapsing whichever edge changes the surface the least (quadric error metric).
+// Vertices that end up unused are dropped, so the result is compact.
+pub fn simplify_mesh(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    target_ratio: f32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let triangle_count = indices.len() / 3;
+    let target = (triangle_count as f32 * target_ratio.clamp(0.0, 1.0)) as usize;
+    if target >= triangle_count {
+        return (vertices.to_vec(), indices.to_vec());
+    }
+
+    let mut vertices = vertices.to_vec();
+    let mut triangles: Vec<[usize; 3]> = indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            [
+                triangle[0] as usize,
+                triangle[1] as usize,
+                triangle[2] as usize,
+            ]
+        })
+        .collect();
+    let mut removed = vec![false; triangles.len()];
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    let mut vertex_triangles = vec![Vec::new(); vertices.len()];
+    let mut stamps = vec![0u32; vertices.len()];
+
+    // Each vertex starts with the error of the planes of every triangle around it
+    for (triangle_index, triangle) in triangles.iter().enumerate() {
+        let [p0, p1, p2] = triangle.map(|index| vertices[index].position);
+        let normal = face_normal(p0, p1, p2);
+        if normal.magnitude2() > 0.0 {
+            let normal = normal.normalize().cast::<f64>().unwrap();
+            let d = -normal.dot(cgmath::Vector3::from(p0).cast::<f64>().unwrap());
+            let plane = Quadric::from_plane(normal.x, normal.y, normal.z, d);
+            for &index in triangle {
+                quadrics[index] = quadrics[index].add(&plane);
+            }
+        }
+        for &index in triangle {
+            vertex_triangles[index].push(triangle_index);
+        }
+    }
+
+    let mut edges = HashSet::new();
+    for triangle in &triangles {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    let mut heap: BinaryHeap<Collapse> = edges
+        .into_iter()
+        .map(|(a, b)| plan_collapse(&vertices, &quadrics, &stamps, a, b))
+        .collect();
+
+    let mut live_triangles = triangle_count;
+    while live_triangles > target {
+        let collapse = match heap.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        let (a, b) = (collapse.a, collapse.b);
+        if collapse.stamps != (stamps[a], stamps[b]) {
+            continue;
+        }
+        let merged = lerp_vertex(&vertices[a], &vertices[b], collapse.t);
+
+        // Skip collapses that would turn a neighbouring triangle inside out
+        let flips = vertex_triangles[a]
+            .iter()
+            .chain(&vertex_triangles[b])
+            .filter(|&&triangle_index| !removed[triangle_index])
+            .map(|&triangle_index| triangles[triangle_index])
+            .filter(|triangle| !(triangle.contains(&a) && triangle.contains(&b)))
+            .any(|triangle| {
+                let before = triangle.map(|index| vertices[index].position);
+                let after = triangle.map(|index| {
+                    if index == a || index == b {
+                        merged.position
+                    } else {
+                        vertices[index].position
+                    }
+                });
+                let before = face_normal(before[0], before[1], before[2]);
+                let after = face_normal(after[0], after[1], after[2]);
+                before.dot(after) < 0.0
+            });
+        if flips {
+            continue;
+        }
+
+        vertices[a] = merged;
+        quadrics[a] = quadrics[a].add(&quadrics[b]);
+        stamps[a] += 1;
+        stamps[b] += 1;
+
+        // Triangles on the edge disappear, the rest of `b`'s move over to `a`
+        for triangle_index in std::mem::take(&mut vertex_triangles[b]) {
+            if removed[triangle_index] {
+                continue;
+            }
+            let triangle = &mut triangles[triangle_index];
+            if triangle.contains(&a) {
+                removed[triangle_index] = true;
+                live_triangles -= 1;
+                continue;
+            }
+            for index in triangle.iter_mut() {
+                if *index == b {
+                    *index = a;
+                }
+            }
+            vertex_triangles[a].push(triangle_index);
+        }
+        vertex_triangles[a].retain(|&triangle_index| !removed[triangle_index]);
+
+        // Every edge touching `a` has a new cost now
+        let mut neighbours: Vec<usize> = vertex_triangles[a]
+            .iter()
+            .flat_map(|&triangle_index| triangles[triangle_index])
+            .filter(|&index| index != a)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            heap.push(plan_collapse(&vertices, &quadrics, &stamps, a, neighbour));
+        }
+    }
+
+    // Only keep the vertices that are still used
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut simplified_vertices = Vec::new();
+    let mut simplified_indices = Vec::with_capacity(live_triangles * 3);
+    for (triangle, _) in triangles
+        .iter()
+        .zip(&removed)
+        .filter(|(_, &removed)| !removed)
+    {
+        for &index in triangle {
+            if remap[index] == u32::MAX {
+                remap[index] = simplified_vertices.len() as u32;
+                simplified_vertices.push(vertices[index]);
+            }
+            simplified_indices.push(remap[index]);
+        }
+    }
+    (simplified_vertices, simplified_indices)
+}
+
 pub trait DrawModel<'a> {
-    fn draw_mesh(
+    fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
         material: &'a Material,
+        instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_mesh_instanced(
+
+    fn draw_model_instanced(
         &mut self,
-        mesh: &'a Mesh,
-        material: &'a Material,
+        model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
 
-    fn draw_model(
+    // Same as the above, but for pipelines that read vertices from a storage buffer
+    fn draw_mesh_instanced_pulled(
         &mut self,
-        model: &'a Model,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_model_instanced(
+    fn draw_model_instanced_pulled(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
@@ -96,16 +1106,6 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
-    }
-
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
@@ -122,16 +1122,50 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
-    fn draw_model(
+    fn draw_model_instanced(
         &mut self,
         model: &'b Model,
+        instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
     }
 
-    fn draw_model_instanced(
+    fn draw_mesh_instanced_pulled(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        let vertex_pull_bind_group = match &mesh.vertex_pull_bind_group {
+            Some(bind_group) => bind_group,
+            None => {
+                log::warn!("Mesh {} doesn't have vertex pulling enabled", mesh.name);
+                return;
+            }
+        };
+        // No vertex buffer in slot 0 - the index buffer still picks which vertex to read
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.set_bind_group(3, vertex_pull_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model_instanced_pulled(
         &mut self,
         model: &'b Model,
         instances: Range<u32>,
@@ -140,7 +1174,7 @@ where
     ) {
         for mesh in &model.meshes {
             let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(
+            self.draw_mesh_instanced_pulled(
                 mesh,
                 material,
                 instances.clone(),
@@ -152,29 +1186,9 @@ where
 }
 
 pub trait DrawLight<'a> {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_light_mesh_instanced(
         &mut self,
-        mesh: &'a Mesh,
-        instances: Range<u32>,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
-
-    fn draw_light_model(
-        &mut self,
-        model: &'a Model,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'a Model,
+        mesh: &'a DynamicPrimitiveMesh,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -185,18 +1199,9 @@ impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_mesh_instanced(mesh, 0..1, camera_bind_group, light_bind_group);
-    }
-
     fn draw_light_mesh_instanced(
         &mut self,
-        mesh: &'b Mesh,
+        mesh: &'b DynamicPrimitiveMesh,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
@@ -207,29 +1212,49 @@ where
         self.set_bind_group(1, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
+}
 
-    fn draw_light_model(
-        &mut self,
-        model: &'b Model,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, x: f32) -> Node {
+        let mut node = Node::new(name.to_string(), 0..0);
+        node.set_position(cgmath::Vector3::new(x, 0.0, 0.0));
+        node
     }
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'b Model,
-        instances: Range<u32>,
-        camera_bind_group: &'b wgpu::BindGroup,
-        light_bind_group: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &model.meshes {
-            self.draw_light_mesh_instanced(
-                mesh,
-                instances.clone(),
-                camera_bind_group,
-                light_bind_group,
-            );
+
+    fn scene(nodes: Vec<Node>) -> Scene {
+        Scene {
+            model: Model {
+                meshes: Vec::new(),
+                materials: Vec::new(),
+            },
+            nodes,
         }
     }
+
+    #[test]
+    fn reparented_nodes_follow_their_new_parent() {
+        // The child comes first, so the parent has to be sorted in front of it
+        let mut scene = scene(vec![node("child", 1.0), node("parent", 2.0)]);
+        scene.compute_world_transforms();
+        scene.set_parent(0, 1);
+        let transforms = scene.compute_world_transforms();
+        assert_eq!(transforms[0].w.x, 3.0);
+
+        // Only the parent is dirty, the child still has to move with it
+        scene.nodes[1].set_position(cgmath::Vector3::new(5.0, 0.0, 0.0));
+        let transforms = scene.compute_world_transforms();
+        assert_eq!(transforms[0].w.x, 6.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "its own ancestor")]
+    fn parent_loops_panic() {
+        let mut scene = scene(vec![node("a", 0.0), node("b", 0.0)]);
+        scene.set_parent(1, 0);
+        scene.set_parent(0, 1);
+        scene.compute_world_transforms();
+    }
 }
@@ -0,0 +1,362 @@
+use std::rc::Rc;
+
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::{texture, viewport};
+
+// A texture projected onto the scene (bullet holes, tire tracks, etc).
+// `world_transform` places the unit cube (0 to 1 on each axis) in the world -
+// the texture is projected down its Z axis onto anything inside it.
+pub struct Decal {
+    pub world_transform: cgmath::Matrix4<f32>,
+    pub texture: Rc<texture::Texture>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    viewport: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalUniform {
+    model: [[f32; 4]; 4],
+    inv_model: [[f32; 4]; 4],
+}
+
+// The unit cube, wound counter clockwise from the outside
+const CUBE_VERTICES: [[f32; 3]; 8] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [0.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0],
+];
+#[rustfmt::skip]
+const CUBE_INDICES: [u16; 36] = [
+    0, 2, 1, 1, 2, 3, // -Z
+    4, 5, 6, 5, 7, 6, // +Z
+    0, 4, 2, 2, 4, 6, // -X
+    1, 3, 5, 3, 7, 5, // +X
+    0, 1, 4, 1, 5, 4, // -Y
+    2, 6, 3, 3, 6, 7, // +Y
+];
+
+struct GpuDecal {
+    _buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+// Draws decals after the main pass by reading back the depth buffer, so the
+// meshes they land on don't need to know about them. Each decal is a box that
+// gets drawn with alpha blending over the scene.
+pub struct DecalPass {
+    pipeline: wgpu::RenderPipeline,
+    depth_layout: wgpu::BindGroupLayout,
+    decal_layout: wgpu::BindGroupLayout,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // One per viewport
+    views: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    depth_bind_group: Option<wgpu::BindGroup>,
+    decals: Vec<GpuDecal>,
+}
+
+impl DecalPass {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, max_views: usize) -> Self {
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let view_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(
+                0,
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            )],
+            label: Some("decal_view_bind_group_layout"),
+        });
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("decal_depth_bind_group_layout"),
+        });
+        let decal_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("decal_bind_group_layout"),
+        });
+
+        let views = (0..max_views)
+            .map(|_| {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Decal View Buffer"),
+                    size: std::mem::size_of::<ViewUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &view_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("decal_view_bind_group"),
+                });
+                (buffer, bind_group)
+            })
+            .collect();
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Vertex Buffer"),
+            contents: bytemuck::cast_slice(&CUBE_VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Index Buffer"),
+            contents: bytemuck::cast_slice(&CUBE_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[&view_layout, &depth_layout, &decal_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Decal Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/decal.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Decal Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Only the back faces, so each pixel is shaded once and
+                // the decal still shows up when the camera is inside the box
+                cull_mode: Some(wgpu::Face::Front),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // The depth buffer is bound as a texture, so it can't be an attachment too
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            depth_layout,
+            decal_layout,
+            vertex_buffer,
+            index_buffer,
+            views,
+            depth_bind_group: None,
+            decals: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    pub fn add(&mut self, device: &wgpu::Device, decal: Decal) {
+        let inv_model = decal
+            .world_transform
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Buffer"),
+            contents: bytemuck::cast_slice(&[DecalUniform {
+                model: decal.world_transform.into(),
+                inv_model: inv_model.into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.decal_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&decal.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&decal.texture.sampler),
+                },
+            ],
+            label: Some("decal_bind_group"),
+        });
+        self.decals.push(GpuDecal {
+            _buffer: buffer,
+            bind_group,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    // Needs to be called again whenever the depth texture is recreated
+    pub fn set_depth(&mut self, device: &wgpu::Device, depth_texture: &texture::Texture) {
+        // Formats with stencil can only be sampled through a depth only view
+        let depth_view = depth_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor {
+                aspect: wgpu::TextureAspect::DepthOnly,
+                ..Default::default()
+            });
+        self.depth_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.depth_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth_view),
+            }],
+            label: Some("decal_depth_bind_group"),
+        }));
+    }
+
+    // The camera and viewport of each view, in the same order `draw` gets the rects
+    pub fn set_views(
+        &self,
+        queue: &wgpu::Queue,
+        views: &[(cgmath::Matrix4<f32>, viewport::ViewportRect)],
+    ) {
+        for ((view_proj, rect), (buffer, _)) in views.iter().zip(self.views.iter()) {
+            let inv_view_proj = view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::cast_slice(&[ViewUniform {
+                    view_proj: (*view_proj).into(),
+                    inv_view_proj: inv_view_proj.into(),
+                    viewport: [
+                        rect.x as f32,
+                        rect.y as f32,
+                        rect.width as f32,
+                        rect.height as f32,
+                    ],
+                }]),
+            );
+        }
+    }
+
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        rects: &[viewport::ViewportRect],
+    ) {
+        let depth_bind_group = match &self.depth_bind_group {
+            Some(depth_bind_group) => depth_bind_group,
+            None => return,
+        };
+        if self.decals.is_empty() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Decal Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(1, depth_bind_group, &[]);
+
+        for (rect, (_, view_bind_group)) in rects.iter().zip(self.views.iter()) {
+            if rect.is_empty() {
+                continue;
+            }
+            render_pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            render_pass.set_bind_group(0, view_bind_group, &[]);
+            for gpu_decal in &self.decals {
+                render_pass.set_bind_group(2, &gpu_decal.bind_group, &[]);
+                render_pass.draw_indexed(0..CUBE_INDICES.len() as u32, 0, 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::{texture, viewport};
+
+// Settings for `FogPass`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub color: [f32; 3],
+    // Higher is thicker. Fog covers `1 - exp(-(density * distance)^2)` of a surface.
+    pub density: f32,
+    // Fog is full strength below this world space height...
+    pub height: f32,
+    // ...and fades out above it at this rate. 0 keeps it the same at every height.
+    pub height_falloff: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: [0.5, 0.6, 0.7],
+            density: 0.05,
+            height: 0.0,
+            height_falloff: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    viewport: [f32; 4],
+    camera_position: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FogUniform {
+    color: [f32; 4],
+    density: f32,
+    height: f32,
+    height_falloff: f32,
+    // Uniforms need to be a multiple of 16 bytes
+    _padding: u32,
+}
+
+impl From<Fog> for FogUniform {
+    fn from(fog: Fog) -> Self {
+        let [r, g, b] = fog.color;
+        Self {
+            color: [r, g, b, 1.0],
+            density: fog.density,
+            height: fog.height,
+            height_falloff: fog.height_falloff,
+            _padding: 0,
+        }
+    }
+}
+
+// Blends fog over the finished scene based on how far away each pixel is, so the
+// shaders that drew the scene don't need to know about it. Reads the depth buffer
+// like `DecalPass`, so it runs after the main pass in a pass of its own.
+pub struct FogPass {
+    pipeline: wgpu::RenderPipeline,
+    depth_layout: wgpu::BindGroupLayout,
+    // One per viewport
+    views: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    depth_bind_group: Option<wgpu::BindGroup>,
+    fog_buffer: wgpu::Buffer,
+    fog_bind_group: wgpu::BindGroup,
+    fog: Fog,
+}
+
+impl FogPass {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        max_views: usize,
+        fog: Fog,
+    ) -> Self {
+        let uniform_layout = |label| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some(label),
+            })
+        };
+        let view_layout = uniform_layout("fog_view_bind_group_layout");
+        let fog_layout = uniform_layout("fog_bind_group_layout");
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("fog_depth_bind_group_layout"),
+        });
+
+        let views = (0..max_views)
+            .map(|_| {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Fog View Buffer"),
+                    size: std::mem::size_of::<ViewUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &view_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("fog_view_bind_group"),
+                });
+                (buffer, bind_group)
+            })
+            .collect();
+
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[FogUniform::from(fog)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let fog_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &fog_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fog_buffer.as_entire_binding(),
+            }],
+            label: Some("fog_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fog Pipeline Layout"),
+            bind_group_layouts: &[&view_layout, &depth_layout, &fog_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fog Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/fog.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fog Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // The depth buffer is bound as a texture, so it can't be an attachment too
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            depth_layout,
+            views,
+            depth_bind_group: None,
+            fog_buffer,
+            fog_bind_group,
+            fog,
+        }
+    }
+
+    pub fn fog(&self) -> Fog {
+        self.fog
+    }
+
+    pub fn set_fog(&mut self, fog: Fog, queue: &wgpu::Queue) {
+        self.fog = fog;
+        queue.write_buffer(
+            &self.fog_buffer,
+            0,
+            bytemuck::cast_slice(&[FogUniform::from(fog)]),
+        );
+    }
+
+    // Needs to be called again whenever the depth texture is recreated
+    pub fn set_depth(&mut self, device: &wgpu::Device, depth_texture: &texture::Texture) {
+        // Formats with stencil can only be sampled through a depth only view
+        let depth_view = depth_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor {
+                aspect: wgpu::TextureAspect::DepthOnly,
+                ..Default::default()
+            });
+        self.depth_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.depth_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth_view),
+            }],
+            label: Some("fog_depth_bind_group"),
+        }));
+    }
+
+    // The camera position, view projection and viewport of each view,
+    // in the same order `draw` gets the rects
+    pub fn set_views(
+        &self,
+        queue: &wgpu::Queue,
+        views: &[(
+            cgmath::Point3<f32>,
+            cgmath::Matrix4<f32>,
+            viewport::ViewportRect,
+        )],
+    ) {
+        for ((eye, view_proj, rect), (buffer, _)) in views.iter().zip(self.views.iter()) {
+            let inv_view_proj = view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::cast_slice(&[ViewUniform {
+                    inv_view_proj: inv_view_proj.into(),
+                    viewport: [
+                        rect.x as f32,
+                        rect.y as f32,
+                        rect.width as f32,
+                        rect.height as f32,
+                    ],
+                    camera_position: [eye.x, eye.y, eye.z, 1.0],
+                }]),
+            );
+        }
+    }
+
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        rects: &[viewport::ViewportRect],
+    ) {
+        let depth_bind_group = match &self.depth_bind_group {
+            Some(depth_bind_group) => depth_bind_group,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Fog Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, depth_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.fog_bind_group, &[]);
+
+        for (rect, (_, view_bind_group)) in rects.iter().zip(self.views.iter()) {
+            if rect.is_empty() {
+                continue;
+            }
+            render_pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            render_pass.set_bind_group(0, view_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use wgpu::util::DeviceExt;
+
+// Threads per workgroup - must match `@workgroup_size` in the shader
+const WORKGROUP_SIZE: u32 = 256;
+// Sorts to the back, so the padding never swaps into the real data
+const PADDING_KEY: f32 = f32::MAX;
+const PADDING_VALUE: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SortParams {
+    j: u32,
+    k: u32,
+    padded_count: u32,
+    _padding: u32,
+}
+
+// Sorts a buffer of `f32` keys (e.g. distance to camera) along with a buffer
+// of `u32` values (e.g. indices into the `InstanceRaw` buffer) using a bitonic sort.
+// Sorting is ascending - write negative distances to get back-to-front order for transparency.
+// The sort runs on copies of the buffers it owns, so it doesn't need a bind group for every
+// buffer it's given, and the buffers don't need room for padding.
+// Compute shaders aren't available on WebGL, so this only works on native and WebGPU.
+pub struct GpuSort {
+    step_pipeline: wgpu::ComputePipeline,
+    // Each dispatch reads its params from a different offset in the params buffer.
+    // Every power of 2 up to the capacity has its steps baked in, starting at `level_offsets`.
+    params_stride: wgpu::BufferAddress,
+    level_offsets: Vec<wgpu::BufferAddress>,
+    // Where the sorting happens, big enough for the padded capacity
+    keys: wgpu::Buffer,
+    values: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Copied after the real elements to pad them out to a power of 2
+    padding_keys: wgpu::Buffer,
+    padding_values: wgpu::Buffer,
+    // Most elements `sort` can handle, without the padding
+    capacity: u32,
+}
+
+impl GpuSort {
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Keys
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Values
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Params for the current step
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<SortParams>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("gpu_sort_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Sort Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Sort Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/gpu_sort.wgsl").into()),
+        });
+
+        let step_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Sort Step Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "bitonic_step",
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+        let params_size = std::mem::size_of::<SortParams>() as wgpu::BufferAddress;
+        let params_stride = params_size.div_ceil(alignment) * alignment;
+
+        // The params only depend on the padded count, so every sort up to the capacity
+        // can use them without writing anything while recording.
+        // `queue.write_buffer` would land before the whole encoder runs anyway, not between dispatches.
+        let max_padded_count = capacity.max(2).next_power_of_two();
+        let mut level_offsets = Vec::new();
+        let mut params_data = Vec::new();
+        let mut padded_count = 2;
+        while padded_count <= max_padded_count {
+            level_offsets.push(params_data.len() as wgpu::BufferAddress);
+            for step in Self::steps(padded_count) {
+                let start = params_data.len();
+                params_data.resize(start + params_stride as usize, 0);
+                params_data[start..start + params_size as usize]
+                    .copy_from_slice(bytemuck::bytes_of(&step));
+            }
+            padded_count *= 2;
+        }
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Sort Params Buffer"),
+            contents: &params_data,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // There's always less padding than real elements
+        let max_padding = (max_padded_count / 2) as usize;
+        let padding_keys = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Sort Padding Keys"),
+            contents: bytemuck::cast_slice(&vec![PADDING_KEY; max_padding]),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        let padding_values = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Sort Padding Values"),
+            contents: bytemuck::cast_slice(&vec![PADDING_VALUE; max_padding]),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let element_size = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let sort_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: max_padded_count as wgpu::BufferAddress * element_size,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let keys = sort_buffer("GPU Sort Keys");
+        let values = sort_buffer("GPU Sort Values");
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: keys.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: values.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &params_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(params_size),
+                    }),
+                },
+            ],
+            label: Some("gpu_sort_bind_group"),
+        });
+
+        Self {
+            step_pipeline,
+            params_stride,
+            level_offsets,
+            keys,
+            values,
+            bind_group,
+            padding_keys,
+            padding_values,
+            capacity,
+        }
+    }
+
+    // The bitonic network is log2(N) merge stages, with stage `k` needing log2(k) steps
+    fn steps(padded_count: u32) -> Vec<SortParams> {
+        let mut steps = Vec::new();
+        let mut k = 2;
+        while k <= padded_count {
+            let mut j = k / 2;
+            while j > 0 {
+                steps.push(SortParams {
+                    j,
+                    k,
+                    padded_count,
+                    _padding: 0,
+                });
+                j /= 2;
+            }
+            k *= 2;
+        }
+        steps
+    }
+
+    // Records the sort of the first `count` elements of `key_buffer` and `value_buffer` into `encoder`.
+    // Both need `COPY_SRC | COPY_DST` usage. Anything past `count`, or past the capacity
+    // this was made with, is left where it is.
+    pub fn sort(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        key_buffer: &wgpu::Buffer,
+        value_buffer: &wgpu::Buffer,
+        count: u32,
+    ) {
+        let count = count.min(self.capacity);
+        if count < 2 {
+            return;
+        }
+        let padded_count = count.next_power_of_two();
+
+        let element_size = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let size = count as wgpu::BufferAddress * element_size;
+        encoder.copy_buffer_to_buffer(key_buffer, 0, &self.keys, 0, size);
+        encoder.copy_buffer_to_buffer(value_buffer, 0, &self.values, 0, size);
+        let padding = (padded_count - count) as wgpu::BufferAddress * element_size;
+        if padding > 0 {
+            encoder.copy_buffer_to_buffer(&self.padding_keys, 0, &self.keys, size, padding);
+            encoder.copy_buffer_to_buffer(&self.padding_values, 0, &self.values, size, padding);
+        }
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GPU Sort Pass"),
+            });
+            compute_pass.set_pipeline(&self.step_pipeline);
+            let workgroups = padded_count.div_ceil(WORKGROUP_SIZE);
+            // log2(N) stages of 1, 2, ... log2(N) steps, see `steps`
+            let stages = padded_count.trailing_zeros();
+            let step_count = stages * (stages + 1) / 2;
+            // Levels start at a padded count of 2
+            let level_offset = self.level_offsets[stages as usize - 1];
+            for i in 0..step_count as wgpu::BufferAddress {
+                let offset = level_offset + i * self.params_stride;
+                compute_pass.set_bind_group(0, &self.bind_group, &[offset as u32]);
+                compute_pass.dispatch_workgroups(workgroups, 1, 1);
+            }
+        }
+
+        // The padding sorted to the back, so the real elements are all at the front
+        encoder.copy_buffer_to_buffer(&self.keys, 0, key_buffer, 0, size);
+        encoder.copy_buffer_to_buffer(&self.values, 0, value_buffer, 0, size);
+    }
+}
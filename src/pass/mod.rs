@@ -0,0 +1,14 @@
+pub mod background;
+pub mod decal;
+pub mod depth_prepass;
+pub mod fog;
+pub mod gpu_sort;
+pub mod light_gizmo;
+pub mod normal;
+pub mod outline;
+pub mod pick;
+pub mod shadow;
+pub mod thick_line;
+pub mod tonemap;
+pub mod transparent;
+pub mod viewport_outline;
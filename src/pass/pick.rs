@@ -0,0 +1,275 @@
+use std::ops::Range;
+use std::sync::mpsc;
+
+use crate::instance::InstanceRaw;
+use crate::model::{self, Vertex};
+use crate::texture;
+use crate::viewport::ViewportRect;
+
+// Figures out what's under the mouse by drawing instance IDs into an integer texture
+// and reading back a single pixel, instead of intersecting rays with the meshes on the CPU.
+pub struct PickPass {
+    pipeline: wgpu::RenderPipeline,
+    id_texture: texture::Texture,
+    depth_texture: texture::Texture,
+    // Just big enough for one pixel
+    staging_buffer: wgpu::Buffer,
+    // Set while `staging_buffer` is being mapped for a `request_pixel`
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl PickPass {
+    const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Uint;
+    const PIXEL_SIZE: wgpu::BufferAddress = 16;
+
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Pick Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/pick.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Pick Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // Integer targets can't blend
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Self::ID_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let (id_texture, depth_texture) = Self::create_textures(device, config);
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Staging Buffer"),
+            size: Self::PIXEL_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            id_texture,
+            depth_texture,
+            staging_buffer,
+            pending: None,
+        }
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (texture::Texture, texture::Texture) {
+        let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Pick ID Texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::ID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let id_texture = texture::Texture {
+            view: id_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            // Never sampled, but `Texture` always carries one
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor::default()),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            format: Self::ID_FORMAT,
+            texture: id_texture,
+        };
+        let depth_texture = texture::Texture::create_depth_texture(
+            device,
+            config,
+            texture::DepthFormat::Depth32Float,
+            "pick_depth_texture",
+        );
+        (id_texture, depth_texture)
+    }
+
+    // Picks happen in window pixels, so the IDs are drawn at the full window size
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.id_texture, self.depth_texture) = Self::create_textures(device, config);
+    }
+
+    // Draws every view's instance IDs. `views` should use the same rects as the screen
+    // (in window pixels) so the pixel under the cursor matches what's visible.
+    pub fn draw<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        views: impl Iterator<Item = (&'a ViewportRect, &'a wgpu::BindGroup)>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Pick Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // 0 means nothing was hit
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for (rect, camera_bind_group) in views {
+            if rect.is_empty() {
+                continue;
+            }
+            render_pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            for mesh in &model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        }
+    }
+
+    // Starts reading back the instance index at (x, y) from the last `draw`, see `poll_pixel`.
+    // Returns false (and does nothing) if (x, y) is off the texture or the last request
+    // hasn't finished yet.
+    pub fn request_pixel(
+        &mut self,
+        x: u32,
+        y: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> bool {
+        if self.pending.is_some()
+            || x >= self.id_texture.size.width
+            || y >= self.id_texture.size.height
+        {
+            return false;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pick Readback Encoder"),
+        });
+        // A single row copy doesn't need `bytes_per_row`, so no padding to worry about
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.id_texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        self.staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.pending = Some(receiver);
+        true
+    }
+
+    // Call once a frame. Checks on the GPU without waiting for it, so the readback finishes
+    // over the next few frames. Returns `None` until then (or if nothing was requested),
+    // afterwards the instance index that was under the pixel, or `Some(None)` for nothing.
+    pub fn poll_pixel(&mut self, device: &wgpu::Device) -> Option<Option<usize>> {
+        let receiver = self.pending.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        self.pending = None;
+
+        if let Err(e) = result {
+            log::warn!("Couldn't map pick buffer: {}", e);
+            return Some(None);
+        }
+        let id = bytemuck::pod_read_unaligned::<u32>(
+            &self.staging_buffer.slice(..).get_mapped_range()[..4],
+        );
+        self.staging_buffer.unmap();
+        // IDs are offset by one so 0 can mean the background
+        Some(id.checked_sub(1).map(|index| index as usize))
+    }
+}
@@ -0,0 +1,311 @@
+use std::ops::Range;
+
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::instance::InstanceRaw;
+use crate::model::{self, Vertex};
+use crate::texture;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    light_view_proj: [[f32; 4]; 4],
+    // Which light in the light array casts the shadow, `u32::MAX` when none does
+    light_index: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 3],
+}
+
+// Square shadow map sizes, bigger ones give sharper shadows
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Low = 512,
+    Medium = 1024,
+    High = 2048,
+    Ultra = 4096,
+}
+
+impl ShadowQuality {
+    pub fn size(self) -> u32 {
+        self as u32
+    }
+
+    // Cycles through every quality, for comparing them at runtime
+    pub fn next(self) -> Self {
+        match self {
+            ShadowQuality::Low => ShadowQuality::Medium,
+            ShadowQuality::Medium => ShadowQuality::High,
+            ShadowQuality::High => ShadowQuality::Ultra,
+            ShadowQuality::Ultra => ShadowQuality::Low,
+        }
+    }
+}
+
+// How one light's shadow map gets rendered
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowConfig {
+    pub width: u32,
+    pub height: u32,
+    // Pushes the stored depth back so surfaces don't shadow themselves (shadow acne),
+    // see `wgpu::DepthBiasState`
+    pub depth_bias: i32,
+    pub depth_bias_slope_scale: f32,
+}
+
+impl ShadowConfig {
+    // A square map the size of `quality`, fails if the device can't make textures that big
+    pub fn new(quality: ShadowQuality, limits: &wgpu::Limits) -> anyhow::Result<Self> {
+        let size = quality.size();
+        if size > limits.max_texture_dimension_2d {
+            anyhow::bail!(
+                "{:?} shadows need {}x{} textures, this device only goes up to {}",
+                quality,
+                size,
+                size,
+                limits.max_texture_dimension_2d
+            );
+        }
+        Ok(Self {
+            width: size,
+            height: size,
+            depth_bias: 2,
+            depth_bias_slope_scale: 2.0,
+        })
+    }
+}
+
+// One light's depth, along with what it took to render it
+struct ShadowMap {
+    config: ShadowConfig,
+    texture: texture::Texture,
+    // The depth bias is part of the pipeline, so every map gets its own
+    pipeline: wgpu::RenderPipeline,
+    uniform: ShadowUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+// Renders the scene's depth from the point of view of the lights that cast shadows, so the
+// main pass can check whether anything sits between a pixel and those lights.
+// There's one shadow map per `ShadowConfig`, each of them shows a single light.
+// Only directional and spot lights cast shadows - a point light would need a cube map.
+pub struct ShadowPass {
+    maps: Vec<ShadowMap>,
+}
+
+impl ShadowPass {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub fn new(device: &wgpu::Device, configs: &[ShadowConfig]) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shadow.wgsl").into()),
+        });
+
+        let maps = configs
+            .iter()
+            .map(|&config| {
+                let uniform = ShadowUniform {
+                    light_view_proj: cgmath::Matrix4::identity().into(),
+                    light_index: u32::MAX,
+                    _padding: [0; 3],
+                };
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shadow Uniform Buffer"),
+                    contents: bytemuck::cast_slice(&[uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Shadow Bind Group"),
+                    layout: &bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                });
+                ShadowMap {
+                    config,
+                    texture: Self::create_texture(device, &config),
+                    pipeline: Self::create_pipeline(device, &layout, &shader, &config),
+                    uniform,
+                    buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        Self { maps }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        config: &ShadowConfig,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            // Depth only - no fragment shader or color targets
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: config.depth_bias,
+                    slope_scale: config.depth_bias_slope_scale,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+
+    fn create_texture(device: &wgpu::Device, config: &ShadowConfig) -> texture::Texture {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Compares against the stored depth, linear filtering blends neighbouring results
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+            size,
+            format: Self::FORMAT,
+        }
+    }
+
+    // Shadow map `index` and its comparison sampler, for the lighting shader to sample
+    pub fn texture(&self, index: usize) -> &texture::Texture {
+        &self.maps[index].texture
+    }
+
+    // Light view projection and which light casts shadow map `index`, for the lighting shader
+    pub fn uniform_buffer(&self, index: usize) -> &wgpu::Buffer {
+        &self.maps[index].buffer
+    }
+
+    pub fn config(&self, index: usize) -> &ShadowConfig {
+        &self.maps[index].config
+    }
+
+    // Picks the light shown in shadow map `index`, `None` leaves that map empty
+    pub fn set_light(
+        &mut self,
+        queue: &wgpu::Queue,
+        index: usize,
+        light: Option<(u32, cgmath::Matrix4<f32>)>,
+    ) {
+        let map = &mut self.maps[index];
+        match light {
+            Some((light_index, light_view_proj)) => {
+                map.uniform.light_index = light_index;
+                map.uniform.light_view_proj = light_view_proj.into();
+            }
+            None => map.uniform.light_index = u32::MAX,
+        }
+        queue.write_buffer(&map.buffer, 0, bytemuck::cast_slice(&[map.uniform]));
+    }
+
+    // Renders every instance of `model` into each shadow map.
+    // `instance_buffer` holds the `InstanceRaw`s, same as the main pass uses.
+    pub fn draw_shadow_maps(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        for map in &self.maps {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &map.texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            // Nothing casts a shadow, the cleared map is all the lighting shader needs
+            if map.uniform.light_index == u32::MAX {
+                continue;
+            }
+            render_pass.set_pipeline(&map.pipeline);
+            render_pass.set_bind_group(0, &map.bind_group, &[]);
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for mesh in &model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::mem;
+
+use wgpu::util::DeviceExt;
+
+// A single line segment, laid out to match the `Line` struct in `thick_line.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ThickLine {
+    pub start: [f32; 3],
+    // Width in pixels
+    pub thickness: f32,
+    pub end: [f32; 3],
+    // Storage buffers align vec3 to 16 bytes, so we need to use a padding field here
+    pub _padding: u32,
+    pub color: [f32; 4],
+}
+
+impl ThickLine {
+    pub fn new(start: [f32; 3], end: [f32; 3], thickness: f32, color: [f32; 4]) -> Self {
+        Self {
+            start,
+            thickness,
+            end,
+            _padding: 0,
+            color,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewportUniform {
+    size: [f32; 2],
+}
+
+// Renders lines as anti-aliased, camera facing quads instead of using `PrimitiveTopology::LineList`
+// (which is always 1 pixel wide) or `PolygonMode::Line` (which requires a device feature).
+// Lines are read from a storage buffer in the vertex shader, so this needs a WebGPU (not WebGL) backend.
+pub struct ThickLinePass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    line_buffer: wgpu::Buffer,
+    viewport_buffer: wgpu::Buffer,
+    // How many lines fit in `line_buffer` before we need to make a bigger one
+    capacity: usize,
+    num_lines: u32,
+}
+
+impl ThickLinePass {
+    // `width` and `height` are the size of the target, see `resize`
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("thick_line_bind_group_layout"),
+        });
+
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Thick Line Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[ViewportUniform {
+                size: [width as f32, height as f32],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Start with room for a handful of lines, `set_lines` grows it as needed
+        let capacity = 64;
+        let line_buffer = Self::create_line_buffer(device, capacity);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &line_buffer, &viewport_buffer);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Thick Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Thick Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/thick_line.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Thick Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                // No vertex buffers - everything is pulled from the storage buffer
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Quads can end up facing either way depending on the line direction
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Test against the scene but don't write, so the soft edges blend over each other
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            line_buffer,
+            viewport_buffer,
+            capacity,
+            num_lines: 0,
+        }
+    }
+
+    fn create_line_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Thick Line Buffer"),
+            size: (capacity * mem::size_of::<ThickLine>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        line_buffer: &wgpu::Buffer,
+        viewport_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: line_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: viewport_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("thick_line_bind_group"),
+        })
+    }
+
+    // Replace the lines we draw. The buffer only gets recreated when it needs to grow.
+    pub fn set_lines(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lines: &[ThickLine]) {
+        if lines.len() > self.capacity {
+            self.capacity = lines.len().next_power_of_two();
+            self.line_buffer = Self::create_line_buffer(device, self.capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.line_buffer,
+                &self.viewport_buffer,
+            );
+        }
+        if !lines.is_empty() {
+            queue.write_buffer(&self.line_buffer, 0, bytemuck::cast_slice(lines));
+        }
+        self.num_lines = lines.len() as u32;
+    }
+
+    // Thickness is in pixels, so we need to know the size of the screen
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[ViewportUniform {
+                size: [width as f32, height as f32],
+            }]),
+        );
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.num_lines == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        // 2 triangles per line, the shader works out which line from the vertex index
+        render_pass.draw(0..self.num_lines * 6, 0..1);
+    }
+}
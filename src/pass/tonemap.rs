@@ -0,0 +1,346 @@
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+// How HDR colors get squeezed into the 0-1 range of the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    // No curve, anything brighter than 1.0 is clipped
+    Linear,
+    Reinhard,
+    // Reinhard, but `max_white` (and anything brighter) maps to pure white
+    ReinhardExtended { max_white: f32 },
+    Aces,
+    Uncharted2,
+}
+
+impl ToneMapOperator {
+    // Has to match the `switch` in `tonemap.wgsl`
+    fn discriminant(&self) -> u32 {
+        match self {
+            ToneMapOperator::Linear => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::ReinhardExtended { .. } => 2,
+            ToneMapOperator::Aces => 3,
+            ToneMapOperator::Uncharted2 => 4,
+        }
+    }
+
+    // Cycles through every operator, handy for comparing them at runtime
+    pub fn next(&self) -> Self {
+        match self {
+            ToneMapOperator::Linear => ToneMapOperator::Reinhard,
+            ToneMapOperator::Reinhard => ToneMapOperator::ReinhardExtended { max_white: 4.0 },
+            ToneMapOperator::ReinhardExtended { .. } => ToneMapOperator::Aces,
+            ToneMapOperator::Aces => ToneMapOperator::Uncharted2,
+            ToneMapOperator::Uncharted2 => ToneMapOperator::Linear,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+    curve: u32,
+    exposure: f32,
+    max_white: f32,
+    encode_gamma: u32,
+}
+
+// Draws the HDR scene texture to the screen (or any other target), applying exposure
+// and a tone mapping curve. The source is sampled with bilinear filtering, so it can
+// be a different size than the target.
+pub struct ToneMapPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>,
+    uniform_buffer: wgpu::Buffer,
+    operator: ToneMapOperator,
+    exposure: f32,
+    // Shading happens in linear space. sRGB targets convert on write, others need the shader to do it.
+    encode_gamma: bool,
+}
+
+impl ToneMapPass {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+
+        // Linear with no exposure change looks the same as rendering straight to the screen
+        let operator = ToneMapOperator::Linear;
+        let exposure = 1.0;
+        let encode_gamma = !target_format.describe().srgb;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Map Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(operator, exposure, encode_gamma)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone Map Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Map Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/tonemap.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone Map Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+            uniform_buffer,
+            operator,
+            exposure,
+            encode_gamma,
+        }
+    }
+
+    fn uniform(operator: ToneMapOperator, exposure: f32, encode_gamma: bool) -> ToneMapUniform {
+        let max_white = match operator {
+            ToneMapOperator::ReinhardExtended { max_white } => max_white,
+            _ => 1.0,
+        };
+        ToneMapUniform {
+            curve: operator.discriminant(),
+            exposure,
+            max_white,
+            encode_gamma: encode_gamma as u32,
+        }
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(
+                self.operator,
+                self.exposure,
+                self.encode_gamma,
+            )]),
+        );
+    }
+
+    pub fn operator(&self) -> ToneMapOperator {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: ToneMapOperator, queue: &wgpu::Queue) {
+        self.operator = operator;
+        self.write_uniform(queue);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    // Multiplies the HDR color before the curve is applied, for every operator
+    pub fn set_exposure(&mut self, exposure: f32, queue: &wgpu::Queue) {
+        self.exposure = exposure;
+        self.write_uniform(queue);
+    }
+
+    // Needs to be called again whenever the source texture is recreated
+    pub fn set_source(&mut self, device: &wgpu::Device, source: &texture::Texture) {
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&source.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        }));
+    }
+
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) => bind_group,
+            None => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Map Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CPU copies of the curves in `tonemap.wgsl`, for a single channel
+    fn uncharted2_partial(x: f32) -> f32 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+
+    fn tonemap(operator: ToneMapOperator, exposure: f32, hdr: f32) -> f32 {
+        let x = hdr * exposure;
+        let mapped = match operator {
+            ToneMapOperator::Linear => x,
+            ToneMapOperator::Reinhard => x / (x + 1.0),
+            ToneMapOperator::ReinhardExtended { max_white } => {
+                x * (1.0 + x / (max_white * max_white)) / (x + 1.0)
+            }
+            ToneMapOperator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+            }
+            ToneMapOperator::Uncharted2 => uncharted2_partial(x * 2.0) / uncharted2_partial(11.2),
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+
+    fn assert_maps(operator: ToneMapOperator, exposure: f32, pairs: &[(f32, f32)]) {
+        for &(hdr, expected) in pairs {
+            let mapped = tonemap(operator, exposure, hdr);
+            assert!(
+                (mapped - expected).abs() < 1e-4,
+                "{operator:?} at exposure {exposure}: {hdr} -> {mapped}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn linear_clips() {
+        assert_maps(
+            ToneMapOperator::Linear,
+            1.0,
+            &[(0.0, 0.0), (0.25, 0.25), (1.0, 1.0), (2.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn reinhard() {
+        assert_maps(
+            ToneMapOperator::Reinhard,
+            1.0,
+            &[(0.0, 0.0), (1.0, 0.5), (3.0, 0.75)],
+        );
+        // Exposure is applied before the curve
+        assert_maps(ToneMapOperator::Reinhard, 2.0, &[(0.5, 0.5), (1.5, 0.75)]);
+    }
+
+    #[test]
+    fn reinhard_extended_maps_max_white_to_white() {
+        assert_maps(
+            ToneMapOperator::ReinhardExtended { max_white: 4.0 },
+            1.0,
+            &[(0.0, 0.0), (1.0, 0.53125), (4.0, 1.0), (10.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn aces() {
+        assert_maps(
+            ToneMapOperator::Aces,
+            1.0,
+            &[(0.0, 0.0), (1.0, 2.54 / 3.16), (100.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn uncharted2_maps_white_point_to_white() {
+        assert_maps(ToneMapOperator::Uncharted2, 1.0, &[(0.0, 0.0), (5.6, 1.0)]);
+    }
+
+    #[test]
+    fn next_cycles_through_every_operator() {
+        let mut operator = ToneMapOperator::Linear;
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            seen.push(operator.discriminant());
+            operator = operator.next();
+        }
+        assert_eq!(operator, ToneMapOperator::Linear);
+        seen.sort();
+        assert_eq!(seen, [0, 1, 2, 3, 4]);
+    }
+}
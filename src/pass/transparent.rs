@@ -0,0 +1,271 @@
+use std::mem;
+
+use wgpu::util::DeviceExt;
+
+use super::gpu_sort::GpuSort;
+use crate::instance::{Instance, InstanceColor, InstanceRaw};
+use crate::model::{self, ModelVertex};
+
+// Threads per workgroup - must match `@workgroup_size` in the shader
+const WORKGROUP_SIZE: u32 = 256;
+
+// Instances of a see-through mesh that get sorted back to front on the GPU every frame,
+// so they blend in the right order without reading anything back.
+// The distances, the sort and putting the instances in order are all compute passes,
+// then the sorted buffers get drawn with `draw_indexed_indirect`.
+// Compute shaders aren't available on WebGL, so this is native (or WebGPU) only.
+pub struct TransparentPass {
+    mesh: model::Mesh,
+    material: model::Material,
+    count: u32,
+    bind_group: wgpu::BindGroup,
+    distance_pipeline: wgpu::ComputePipeline,
+    gather_pipeline: wgpu::ComputePipeline,
+    gpu_sort: GpuSort,
+    // Written by the distance pass, sorted by `gpu_sort`, read by the gather pass
+    key_buffer: wgpu::Buffer,
+    value_buffer: wgpu::Buffer,
+    // Filled in by the gather pass, farthest from the camera first
+    sorted_instance_buffer: wgpu::Buffer,
+    sorted_color_buffer: wgpu::Buffer,
+    indirect_buffer: wgpu::Buffer,
+}
+
+impl TransparentPass {
+    // `wireframe` unwelds the mesh so it works with the barycentric wireframe shader.
+    // Instances are sorted by their distance to the camera in `camera_buffer`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+        (mut vertices, mut indices): (Vec<ModelVertex>, Vec<u32>),
+        instances: &[(Instance, InstanceColor)],
+        wireframe: bool,
+    ) -> Self {
+        if wireframe {
+            crate::primitives::add_barycentric_coordinates(&mut vertices, &mut indices);
+        }
+        let mesh = model::Mesh::new(device, "Transparent", vertices, indices, 0);
+        let diffuse_texture = crate::texture::Texture::from_color(
+            device,
+            queue,
+            [255, 255, 255, 255],
+            "Transparent Diffuse",
+        );
+        let material = model::Material::new(
+            device,
+            queue,
+            "Transparent",
+            diffuse_texture,
+            "",
+            None,
+            None,
+            None,
+            texture_bind_group_layout,
+        );
+
+        let count = instances.len() as u32;
+        let instance_data = instances
+            .iter()
+            .map(|(instance, _)| instance.to_raw())
+            .collect::<Vec<_>>();
+        let color_data = instances
+            .iter()
+            .map(|(_, color)| *color)
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Transparent Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Transparent Color Buffer"),
+            contents: bytemuck::cast_slice(&color_data),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let sorted_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sorted Transparent Instance Buffer"),
+            size: (instances.len() * mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let sorted_color_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sorted Transparent Color Buffer"),
+            size: (instances.len() * mem::size_of::<InstanceColor>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+
+        // The sort copies these in and out of its own buffers
+        let sort_usage = wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST;
+        let key_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Transparent Sort Keys"),
+            size: (instances.len().max(1) * mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: sort_usage,
+            mapped_at_creation: false,
+        });
+        let value_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Transparent Sort Values"),
+            size: (instances.len().max(1) * mem::size_of::<u32>()) as wgpu::BufferAddress,
+            usage: sort_usage,
+            mapped_at_creation: false,
+        });
+        let gpu_sort = GpuSort::new(device, count);
+
+        let indirect_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Transparent Indirect Buffer"),
+            contents: wgpu::util::DrawIndexedIndirect {
+                vertex_count: mesh.num_elements,
+                instance_count: count,
+                base_index: 0,
+                vertex_offset: 0,
+                base_instance: 0,
+            }
+            .as_bytes(),
+            usage: wgpu::BufferUsages::INDIRECT,
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // The camera layout used for drawing isn't visible to compute shaders
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, true),
+                storage(3, false),
+                storage(4, false),
+                storage(5, false),
+                storage(6, false),
+            ],
+            label: Some("transparent_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                camera_buffer,
+                &instance_buffer,
+                &color_buffer,
+                &key_buffer,
+                &value_buffer,
+                &sorted_instance_buffer,
+                &sorted_color_buffer,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+            label: Some("transparent_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Transparent Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Transparent Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/transparent.wgsl").into()),
+        });
+        let distance_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Transparent Distance Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "distances",
+        });
+        let gather_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Transparent Gather Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "gather",
+        });
+
+        Self {
+            mesh,
+            material,
+            count,
+            bind_group,
+            distance_pipeline,
+            gather_pipeline,
+            gpu_sort,
+            key_buffer,
+            value_buffer,
+            sorted_instance_buffer,
+            sorted_color_buffer,
+            indirect_buffer,
+        }
+    }
+
+    // Records the sort into `encoder`, before the render pass that draws them.
+    // Uses wherever the camera buffer is when the encoder runs.
+    pub fn sort(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.count == 0 {
+            return;
+        }
+        let workgroups = self.count.div_ceil(WORKGROUP_SIZE);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Transparent Distance Pass"),
+            });
+            compute_pass.set_pipeline(&self.distance_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+        self.gpu_sort
+            .sort(encoder, &self.key_buffer, &self.value_buffer, self.count);
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Transparent Gather Pass"),
+        });
+        compute_pass.set_pipeline(&self.gather_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups, 1, 1);
+    }
+
+    // `pipeline` should be the main pipeline with alpha blending, drawn after everything opaque.
+    // It tests against the depth buffer without writing to it, so the instances don't hide
+    // each other, they just blend.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.sorted_instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.sorted_color_buffer.slice(..));
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(0, &self.material.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, light_bind_group, &[]);
+        render_pass.draw_indexed_indirect(&self.indirect_buffer, 0);
+    }
+}
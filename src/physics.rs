@@ -0,0 +1,88 @@
+use cgmath::prelude::*;
+
+use crate::instance::Instance;
+
+// A spring connecting 2 instances, pulling (or pushing) them towards `rest_length` apart
+pub struct SpringConstraint {
+    pub node_a: usize,
+    pub node_b: usize,
+    pub rest_length: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    // Length last update, used to work out how fast the spring is stretching for damping
+    previous_length: Option<f32>,
+}
+
+impl SpringConstraint {
+    pub fn new(
+        node_a: usize,
+        node_b: usize,
+        rest_length: f32,
+        stiffness: f32,
+        damping: f32,
+    ) -> Self {
+        Self {
+            node_a,
+            node_b,
+            rest_length,
+            stiffness,
+            damping,
+            previous_length: None,
+        }
+    }
+
+    // Applies Hooke's law (F = -k * (dist - rest_length) * dir) plus damping
+    // and moves both instances by half of the resulting displacement (assuming equal mass)
+    pub fn update(&mut self, dt: f32, instances: &mut [Instance]) {
+        if self.node_a == self.node_b || dt <= 0.0 {
+            return;
+        }
+
+        let delta = instances[self.node_b].position - instances[self.node_a].position;
+        let distance = delta.magnitude();
+        // Both at the same spot, there's no direction to push in
+        if distance == 0.0 {
+            return;
+        }
+        let direction = delta / distance;
+
+        let velocity = match self.previous_length {
+            Some(previous_length) => (distance - previous_length) / dt,
+            None => 0.0,
+        };
+        self.previous_length = Some(distance);
+
+        // Positive force pulls the 2 ends together
+        let force = self.stiffness * (distance - self.rest_length) + self.damping * velocity;
+        let displacement = direction * (force * dt * dt * 0.5);
+
+        instances[self.node_a].position += displacement;
+        instances[self.node_b].position -= displacement;
+    }
+}
+
+// Holds all the springs in the scene and steps them each frame
+#[derive(Default)]
+pub struct SpringSimulator {
+    pub constraints: Vec<SpringConstraint>,
+}
+
+impl SpringSimulator {
+    pub fn add(&mut self, constraint: SpringConstraint) {
+        self.constraints.push(constraint);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    pub fn update(&mut self, dt: f32, instances: &mut [Instance]) {
+        // Respawning can leave fewer instances than a spring was attached to, those springs go
+        self.constraints.retain(|constraint| {
+            constraint.node_a < instances.len() && constraint.node_b < instances.len()
+        });
+        for constraint in self.constraints.iter_mut() {
+            constraint.update(dt, instances);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// Identifies a pipeline by what went into it, so identical configurations can share one
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    shader_hash: u64,
+    config_hash: u64,
+}
+
+impl PipelineKey {
+    // `config` should contain anything that changes the pipeline (formats, vertex layouts, blend, etc)
+    pub fn new(shader_source: &str, config: &impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        shader_source.hash(&mut hasher);
+        let shader_hash = hasher.finish();
+
+        let mut hasher = DefaultHasher::new();
+        config.hash(&mut hasher);
+        let config_hash = hasher.finish();
+
+        Self {
+            shader_hash,
+            config_hash,
+        }
+    }
+}
+
+// How a pipeline's output gets combined with what's already in the target
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // Overwrite, for opaque geometry
+    #[default]
+    Replace,
+    // Regular transparency using the alpha channel
+    AlphaBlend,
+    // Adds light on top, for particles and glows. Alpha still scales how much gets added.
+    Additive,
+    // Darkens, white leaves the target unchanged
+    Multiply,
+    // Lightens, black leaves the target unchanged
+    Screen,
+}
+
+impl BlendMode {
+    // Cycles through every mode, for comparing them at runtime
+    pub fn next(self) -> Self {
+        match self {
+            BlendMode::Replace => BlendMode::AlphaBlend,
+            BlendMode::AlphaBlend => BlendMode::Additive,
+            BlendMode::Additive => BlendMode::Multiply,
+            BlendMode::Multiply => BlendMode::Screen,
+            BlendMode::Screen => BlendMode::Replace,
+        }
+    }
+
+    pub fn blend_state(self) -> wgpu::BlendState {
+        // Multiply and Screen only change how color mixes, alpha builds up coverage as usual
+        let alpha_over = wgpu::BlendComponent::OVER;
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::AlphaBlend => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            // src * dst
+            BlendMode::Multiply => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::Zero,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: alpha_over,
+            },
+            // src + dst - src * dst
+            BlendMode::Screen => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: alpha_over,
+            },
+        }
+    }
+}
+
+// Which lighting model the main shader uses for models
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ShadingModel {
+    // Blinn-Phong with the material's shininess and specular map
+    #[default]
+    Phong,
+    // Cook-Torrance using the material's metallic, roughness and ambient occlusion
+    Pbr,
+}
+
+impl ShadingModel {
+    pub fn next(self) -> Self {
+        match self {
+            ShadingModel::Phong => ShadingModel::Pbr,
+            ShadingModel::Pbr => ShadingModel::Phong,
+        }
+    }
+
+    // Fragment entry point in `shader.wgsl`
+    pub fn fragment_entry_point(self, wireframe: bool) -> &'static str {
+        match (self, wireframe) {
+            (ShadingModel::Phong, false) => "fs_main",
+            (ShadingModel::Phong, true) => "fs_wireframe",
+            (ShadingModel::Pbr, false) => "fs_pbr",
+            (ShadingModel::Pbr, true) => "fs_pbr_wireframe",
+        }
+    }
+}
+
+// The lights are a storage buffer sized by `PhongConfig::max_lights`. WebGL doesn't have storage
+// buffers, so there they're a uniform holding a fixed size array instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub const LIGHTS_BINDING_TYPE: wgpu::BufferBindingType =
+    wgpu::BufferBindingType::Storage { read_only: true };
+#[cfg(target_arch = "wasm32")]
+pub const LIGHTS_BINDING_TYPE: wgpu::BufferBindingType = wgpu::BufferBindingType::Uniform;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn lights_buffer_usage() -> wgpu::BufferUsages {
+    wgpu::BufferUsages::STORAGE
+}
+#[cfg(target_arch = "wasm32")]
+pub fn lights_buffer_usage() -> wgpu::BufferUsages {
+    wgpu::BufferUsages::UNIFORM
+}
+
+// Shaders declare `lights` as a storage buffer with a runtime sized array, which is used as is
+#[cfg(not(target_arch = "wasm32"))]
+pub fn lights_shader_source(source: &str, _max_lights: usize) -> Cow<'_, str> {
+    Cow::Borrowed(source)
+}
+// On the web the binding becomes a uniform and the array gets a length of `max_lights`
+#[cfg(target_arch = "wasm32")]
+pub fn lights_shader_source(source: &str, max_lights: usize) -> Cow<'_, str> {
+    Cow::Owned(
+        source
+            .replace(
+                "var<storage, read> lights: Lights;",
+                "var<uniform> lights: Lights;",
+            )
+            .replace(
+                "lights: array<Light>,",
+                &format!("lights: array<Light, {}>,", max_lights),
+            ),
+    )
+}
+
+// Stores compiled render pipelines so toggling between variants at runtime
+// just swaps which pipeline we use instead of compiling a new one.
+// Pipelines don't depend on the window size, so nothing here needs to change on resize.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn get_or_create(
+        &mut self,
+        key: PipelineKey,
+        create: impl FnOnce() -> wgpu::RenderPipeline,
+    ) -> Rc<wgpu::RenderPipeline> {
+        self.pipelines
+            .entry(key)
+            .or_insert_with(|| Rc::new(create()))
+            .clone()
+    }
+}
@@ -0,0 +1,81 @@
+use super::cone::push_cone_side;
+use super::cylinder::{push_cap, push_side};
+use super::{fix_winding, WindingOrder};
+use crate::instance::{Instance, InstanceColor};
+use crate::model::{compute_tangents, ModelVertex};
+
+// An arrow pointing up the Y axis, starting at the origin: a cylinder for the shaft with
+// a cone on top for the head. Where they meet, the shaft's top ring, the underside of the
+// head and the head's base ring each get their own vertices, so the normals don't get
+// smoothed across the sharp edges.
+pub fn generate_arrow(
+    shaft_radius: f32,
+    shaft_length: f32,
+    head_radius: f32,
+    head_length: f32,
+    sector_count: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sector_count = sector_count.max(3);
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        0.0,
+        shaft_radius,
+        sector_count,
+        false,
+    );
+    push_side(
+        &mut vertices,
+        &mut indices,
+        [shaft_radius, shaft_radius],
+        [shaft_length, 0.0],
+        sector_count,
+    );
+    // The underside of the head, the middle of it is hidden inside the shaft
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        shaft_length,
+        head_radius,
+        sector_count,
+        false,
+    );
+    push_cone_side(
+        &mut vertices,
+        &mut indices,
+        head_radius,
+        [shaft_length, shaft_length + head_length],
+        sector_count,
+    );
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
+
+// Instances that turn a single `generate_arrow` model into an axis gizmo at `position`:
+// a red arrow along X, a green one along Y and a blue one along Z.
+// Meant to go into an instance buffer together with an instance color buffer.
+pub fn axis_gizmo_instances(position: cgmath::Vector3<f32>) -> [(Instance, InstanceColor); 3] {
+    use cgmath::Rotation3;
+
+    let arrow = |rotation, color| (Instance { position, rotation }, InstanceColor { color });
+    [
+        arrow(
+            cgmath::Quaternion::from_angle_z(cgmath::Deg(-90.0)),
+            [1.0, 0.0, 0.0, 1.0],
+        ),
+        arrow(
+            cgmath::Quaternion::from_angle_z(cgmath::Deg(0.0)),
+            [0.0, 1.0, 0.0, 1.0],
+        ),
+        arrow(
+            cgmath::Quaternion::from_angle_x(cgmath::Deg(90.0)),
+            [0.0, 0.0, 1.0, 1.0],
+        ),
+    ]
+}
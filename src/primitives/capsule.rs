@@ -0,0 +1,85 @@
+use std::f32::consts::PI;
+
+use super::{fix_winding, WindingOrder};
+use crate::model::ModelVertex;
+
+// A cylinder along the Y axis with a half sphere on each end, centered on the origin.
+// `height` is only the straight middle part, the whole capsule is `height + 2 * radius` tall.
+// Each half sphere gets `stack_count / 2` stacks, laid out like `generate_sphere`.
+// The rings where they meet the middle part are shared, so there's no seam,
+// and V follows the distance along the outline so the texture doesn't stretch.
+pub fn generate_capsule(
+    radius: f32,
+    height: f32,
+    sector_count: u32,
+    stack_count: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sector_count = sector_count.max(3);
+    let hemisphere_stacks = (stack_count / 2).max(1);
+    let half_height = height.max(0.0) / 2.0;
+    let outline_length = 2.0 * half_height + PI * radius;
+
+    // Top pole down to the top of the middle part, then the bottom of it down to the other pole
+    let stack_step = PI / 2.0 / hemisphere_stacks as f32;
+    let mut rings = Vec::with_capacity((hemisphere_stacks as usize + 1) * 2);
+    for (hemisphere, offset) in [half_height, -half_height].into_iter().enumerate() {
+        for i in 0..=hemisphere_stacks {
+            let stack_angle = PI / 2.0 * (1.0 - hemisphere as f32) - i as f32 * stack_step;
+            // Distance from the top pole along the outline
+            let distance =
+                (PI / 2.0 - stack_angle) * radius + hemisphere as f32 * 2.0 * half_height;
+            rings.push((stack_angle, offset, distance));
+        }
+    }
+
+    let ring_count = rings.len() as u32;
+    let mut vertices = Vec::with_capacity((ring_count * (sector_count + 1)) as usize);
+    for &(stack_angle, offset, distance) in &rings {
+        let ring_radius = stack_angle.cos();
+        let y = stack_angle.sin();
+
+        for j in 0..=sector_count {
+            let sector_angle = j as f32 * 2.0 * PI / sector_count as f32;
+            let normal = [
+                ring_radius * sector_angle.cos(),
+                y,
+                -ring_radius * sector_angle.sin(),
+            ];
+            let tex_coords = [j as f32 / sector_count as f32, distance / outline_length];
+            vertices.push(ModelVertex {
+                position: [
+                    normal[0] * radius,
+                    normal[1] * radius + offset,
+                    normal[2] * radius,
+                ],
+                tex_coords,
+                normal,
+                tex_coords2: tex_coords,
+                barycentric: [0.0; 3],
+                // Same as `generate_sphere`, the poles are too squashed to work it out from the UVs
+                tangent: [-sector_angle.sin(), 0.0, -sector_angle.cos()],
+            });
+        }
+    }
+
+    let mut indices = Vec::new();
+    for i in 0..ring_count - 1 {
+        let ring = i * (sector_count + 1);
+        let next_ring = ring + sector_count + 1;
+        for j in 0..sector_count {
+            let k1 = ring + j;
+            let k2 = next_ring + j;
+            // Only one triangle per sector next to the poles
+            if i != 0 {
+                indices.extend([k1, k2, k1 + 1]);
+            }
+            if i != ring_count - 2 {
+                indices.extend([k1 + 1, k2, k2 + 1]);
+            }
+        }
+    }
+
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
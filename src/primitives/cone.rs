@@ -0,0 +1,76 @@
+use std::f32::consts::PI;
+
+use super::cylinder::{push_cap, ring_direction};
+use super::{fix_winding, vertex, WindingOrder};
+use crate::model::{compute_tangents, ModelVertex};
+
+// A cone along the Y axis with its tip at `height / 2` and the base at `-height / 2`.
+// The tip gets its own vertex for every sector, pointing halfway between the sector's edges,
+// otherwise it would average out to straight up and the side would look flat near the top.
+// U wraps around the side and V runs from the tip (0) to the base (1),
+// the base is mapped flat from below.
+pub fn generate_cone(
+    radius: f32,
+    height: f32,
+    sector_count: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sector_count = sector_count.max(3);
+    let half_height = height / 2.0;
+
+    let mut vertices = Vec::with_capacity((sector_count * 3 + 2) as usize);
+    let mut indices = Vec::with_capacity((sector_count * 6) as usize);
+    push_cone_side(
+        &mut vertices,
+        &mut indices,
+        radius,
+        [-half_height, half_height],
+        sector_count,
+    );
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        -half_height,
+        radius,
+        sector_count,
+        false,
+    );
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
+
+// The slanted side of a cone around the Y axis, from a ring at `heights[0]` up to its tip at
+// `heights[1]`. Leaves the base open.
+pub(super) fn push_cone_side(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    radius: f32,
+    heights: [f32; 2],
+    sector_count: u32,
+) {
+    // How far the side normals lean up, `atan2(radius, height)` from the horizontal
+    let slope = radius.atan2(heights[1] - heights[0]);
+    let side_normal = |[x, _, z]: [f32; 3]| [x * slope.cos(), slope.sin(), z * slope.cos()];
+
+    let start = vertices.len() as u32;
+    for j in 0..=sector_count {
+        let direction = ring_direction(j, sector_count);
+        vertices.push(vertex(
+            [direction[0] * radius, heights[0], direction[2] * radius],
+            side_normal(direction),
+            [j as f32 / sector_count as f32, 1.0],
+        ));
+    }
+    for j in 0..sector_count {
+        let angle = (j as f32 + 0.5) * 2.0 * PI / sector_count as f32;
+        let tip = vertices.len() as u32;
+        vertices.push(vertex(
+            [0.0, heights[1], 0.0],
+            side_normal([angle.cos(), 0.0, -angle.sin()]),
+            [(j as f32 + 0.5) / sector_count as f32, 0.0],
+        ));
+        indices.extend([tip, start + j, start + j + 1]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::f32::consts::PI;
+
+use cgmath::InnerSpace;
+
+use super::{fix_winding, vertex, WindingOrder};
+use crate::model::{compute_tangents, ModelVertex};
+
+// A cylinder along the Y axis, centered on the origin, going from `-height / 2` to `height / 2`.
+// The side and the caps get their own vertices so the edges between them stay sharp.
+// U wraps around the side and V runs from the top (0) to the bottom (1),
+// the caps are mapped flat from above.
+pub fn generate_cylinder(
+    radius: f32,
+    height: f32,
+    sector_count: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sector_count = sector_count.max(3);
+    let half_height = height / 2.0;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    push_side(
+        &mut vertices,
+        &mut indices,
+        [radius, radius],
+        [half_height, -half_height],
+        sector_count,
+    );
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        half_height,
+        radius,
+        sector_count,
+        true,
+    );
+    push_cap(
+        &mut vertices,
+        &mut indices,
+        -half_height,
+        radius,
+        sector_count,
+        false,
+    );
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
+
+// Point on a circle around the Y axis, going the same way as `generate_sphere`
+pub(super) fn ring_direction(sector: u32, sector_count: u32) -> [f32; 3] {
+    let angle = sector as f32 * 2.0 * PI / sector_count as f32;
+    [angle.cos(), 0.0, -angle.sin()]
+}
+
+// A tube around the Y axis from a ring at `heights[0]` to one at `heights[1]`.
+// Different radii make it a cone (or part of one), with the normals tilted to match.
+// The first and last column sit on the same seam, so U can go all the way from 0 to 1.
+pub(super) fn push_side(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    radii: [f32; 2],
+    heights: [f32; 2],
+    sector_count: u32,
+) {
+    // Tilts the normals up when the top is narrower than the bottom
+    let slope = (radii[1] - radii[0]) / (heights[0] - heights[1]);
+    let start = vertices.len() as u32;
+    for j in 0..=sector_count {
+        let [x, _, z] = ring_direction(j, sector_count);
+        let normal = cgmath::Vector3::new(x, slope, z).normalize();
+        for (ring, (radius, y)) in radii.into_iter().zip(heights).enumerate() {
+            vertices.push(vertex(
+                [x * radius, y, z * radius],
+                normal.into(),
+                [j as f32 / sector_count as f32, ring as f32],
+            ));
+        }
+    }
+    for j in 0..sector_count {
+        let top = start + j * 2;
+        let bottom = top + 1;
+        indices.extend([top, bottom, top + 2, top + 2, bottom, bottom + 2]);
+    }
+}
+
+// A flat disc at `y` facing straight up or down, as a fan around its center
+pub(super) fn push_cap(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    y: f32,
+    radius: f32,
+    sector_count: u32,
+    facing_up: bool,
+) {
+    let normal = if facing_up {
+        [0.0, 1.0, 0.0]
+    } else {
+        [0.0, -1.0, 0.0]
+    };
+    let center = vertices.len() as u32;
+    vertices.push(vertex([0.0, y, 0.0], normal, [0.5, 0.5]));
+    for j in 0..sector_count {
+        let [x, _, z] = ring_direction(j, sector_count);
+        vertices.push(vertex(
+            [x * radius, y, z * radius],
+            normal,
+            [0.5 + x * 0.5, 0.5 + z * 0.5],
+        ));
+    }
+    for j in 0..sector_count {
+        let next = (j + 1) % sector_count;
+        indices.extend([center, center + 1 + j, center + 1 + next]);
+    }
+}
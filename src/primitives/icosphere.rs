@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use cgmath::InnerSpace;
+
+use super::{fix_winding, uv_fix_seams, uv_project_spherical, vertex, WindingOrder};
+use crate::model::{compute_tangents, ModelVertex};
+
+// A sphere made of evenly sized triangles, so it doesn't bunch up at the poles like
+// `generate_sphere`. Starts from an icosahedron (12 vertices, 20 faces) and splits every
+// triangle into 4 `subdivisions` times, pushing the new corners out onto the sphere.
+// UVs use `uv_project_spherical`, then `uv_fix_seams` adds a few vertices along the seam,
+// so there are slightly more vertices than the 12 / 42 / 162... of the bare shape.
+pub fn generate_icosphere(
+    radius: f32,
+    subdivisions: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    // The corners of an icosahedron sit on 3 golden rectangles
+    let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut points: Vec<cgmath::Vector3<f32>> = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .into_iter()
+    .map(|point| cgmath::Vector3::from(point).normalize())
+    .collect();
+    #[rustfmt::skip]
+    let mut indices: Vec<u32> = vec![
+        0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11,
+        1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7, 1, 8,
+        3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9,
+        4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9, 8, 1,
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by 2 triangles, so remember which midpoints already exist
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let point = (points[a as usize] + points[b as usize]).normalize();
+                points.push(point);
+                (points.len() - 1) as u32
+            })
+        };
+
+        let mut subdivided = Vec::with_capacity(indices.len() * 4);
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            let [ab, bc, ca] = [midpoint(a, b), midpoint(b, c), midpoint(c, a)];
+            subdivided.extend([a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]);
+        }
+        indices = subdivided;
+    }
+
+    // On a unit sphere the normal is just the position
+    let mut vertices: Vec<ModelVertex> = points
+        .into_iter()
+        .map(|normal| vertex((normal * radius).into(), normal.into(), [0.0; 2]))
+        .collect();
+    uv_project_spherical(&mut vertices);
+    for vertex in vertices.iter_mut() {
+        vertex.tex_coords2 = vertex.tex_coords;
+    }
+    uv_fix_seams(&mut vertices, &mut indices);
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::InnerSpace;
+
+use crate::model::ModelVertex;
+
+pub mod arrow;
+pub mod capsule;
+pub mod cone;
+pub mod cylinder;
+pub mod icosphere;
+pub mod plane;
+pub mod torus;
+
+// Which way a triangle's corners go around when looking at its front face
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WindingOrder {
+    Clockwise,
+    // What the pipelines treat as the front (`wgpu::FrontFace::Ccw`)
+    #[default]
+    CounterClockwise,
+}
+
+// Vertex for the generators, tangents get filled in afterwards by `compute_tangents`
+fn vertex(position: [f32; 3], normal: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+    ModelVertex {
+        position,
+        tex_coords,
+        normal,
+        tex_coords2: tex_coords,
+        barycentric: [0.0; 3],
+        tangent: [0.0; 3],
+    }
+}
+
+// The axis a planar projection looks down
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+// Projects every vertex onto the plane facing `axis` and uses the
+// remaining 2 coordinates as UVs. `scale` controls how often the texture repeats.
+pub fn uv_project_planar(vertices: &mut [ModelVertex], axis: Axis, scale: f32) {
+    for vertex in vertices.iter_mut() {
+        let [x, y, z] = vertex.position;
+        let (u, v) = match axis {
+            Axis::X => (z, y),
+            Axis::Y => (x, z),
+            Axis::Z => (x, y),
+        };
+        vertex.tex_coords = [u * scale, v * scale];
+    }
+}
+
+// Wraps the texture around the Y axis like a label on a can.
+// U goes 0 -> 1 around the circumference, V repeats along the height by `scale`.
+pub fn uv_project_cylindrical(vertices: &mut [ModelVertex], scale: f32) {
+    for vertex in vertices.iter_mut() {
+        let [x, y, z] = vertex.position;
+        let u = 0.5 + z.atan2(x) / (2.0 * PI);
+        vertex.tex_coords = [u, y * scale];
+    }
+}
+
+// Maps the texture like a globe (latitude / longitude) around the origin
+pub fn uv_project_spherical(vertices: &mut [ModelVertex]) {
+    for vertex in vertices.iter_mut() {
+        let [x, y, z] = vertex.position;
+        let length = (x * x + y * y + z * z).sqrt();
+        if length == 0.0 {
+            vertex.tex_coords = [0.5, 0.5];
+            continue;
+        }
+        let u = 0.5 + z.atan2(x) / (2.0 * PI);
+        let v = 0.5 - (y / length).asin() / PI;
+        vertex.tex_coords = [u, v];
+    }
+}
+
+// Cylindrical and spherical projections wrap U from 1 back to 0, so a triangle
+// crossing the seam would interpolate across the entire texture.
+// We find those triangles and point their low U vertices at a duplicate shifted by 1.0.
+pub fn uv_fix_seams(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    // Keep track of duplicates so vertices shared by several seam triangles are only copied once
+    let mut duplicates: HashMap<u32, u32> = HashMap::new();
+
+    for triangle in indices.chunks_exact_mut(3) {
+        let us = [
+            vertices[triangle[0] as usize].tex_coords[0],
+            vertices[triangle[1] as usize].tex_coords[0],
+            vertices[triangle[2] as usize].tex_coords[0],
+        ];
+        let min = us.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = us.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+
+        // Neighbouring vertices are never more than half the texture apart, unless they wrap
+        if max - min <= 0.5 {
+            continue;
+        }
+
+        for index in triangle.iter_mut() {
+            if vertices[*index as usize].tex_coords[0] >= 0.5 {
+                continue;
+            }
+            *index = *duplicates.entry(*index).or_insert_with(|| {
+                let mut vertex = vertices[*index as usize];
+                vertex.tex_coords[0] += 1.0;
+                vertices.push(vertex);
+                (vertices.len() - 1) as u32
+            });
+        }
+    }
+}
+
+// Gives every triangle its own 3 vertices with barycentric coordinates of
+// (1,0,0), (0,1,0) and (0,0,1), so the wireframe shader can tell how close it is to an edge.
+// Shared vertices can't have a different value per triangle, so the mesh gets unwelded
+// and `indices` is rewritten to point at the new vertices.
+pub fn add_barycentric_coordinates(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    const CORNERS: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    let mut unwelded = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact_mut(3) {
+        for (index, barycentric) in triangle.iter_mut().zip(CORNERS) {
+            let mut vertex = vertices[*index as usize];
+            vertex.barycentric = barycentric;
+            *index = unwelded.len() as u32;
+            unwelded.push(vertex);
+        }
+    }
+    *vertices = unwelded;
+}
+
+// A UV sphere centered on the origin, built from `stack_count` rings from pole to pole,
+// each split into `sector_count` segments. The first and last column of every ring sit on
+// the same seam, so U can go all the way from 0 to 1.
+// Triangles wind in `winding` order when seen from outside.
+pub fn generate_sphere(
+    radius: f32,
+    sector_count: u32,
+    stack_count: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    // Anything less doesn't enclose a volume
+    let sector_count = sector_count.max(3);
+    let stack_count = stack_count.max(2);
+
+    let mut vertices = Vec::with_capacity(((stack_count + 1) * (sector_count + 1)) as usize);
+    for i in 0..=stack_count {
+        // From the top pole (PI / 2) to the bottom one (-PI / 2)
+        let stack_angle = PI / 2.0 - i as f32 * PI / stack_count as f32;
+        let ring_radius = stack_angle.cos();
+        let y = stack_angle.sin();
+
+        for j in 0..=sector_count {
+            let sector_angle = j as f32 * 2.0 * PI / sector_count as f32;
+            let normal = [
+                ring_radius * sector_angle.cos(),
+                y,
+                -ring_radius * sector_angle.sin(),
+            ];
+            let tex_coords = [
+                j as f32 / sector_count as f32,
+                i as f32 / stack_count as f32,
+            ];
+            vertices.push(ModelVertex {
+                position: normal.map(|n| n * radius),
+                tex_coords,
+                normal,
+                tex_coords2: tex_coords,
+                barycentric: [0.0; 3],
+                // Which way the sector angle (and U) goes around
+                tangent: [-sector_angle.sin(), 0.0, -sector_angle.cos()],
+            });
+        }
+    }
+
+    let mut indices = Vec::new();
+    for i in 0..stack_count {
+        // Start of this ring and the one below it
+        let ring = i * (sector_count + 1);
+        let next_ring = ring + sector_count + 1;
+        for j in 0..sector_count {
+            let k1 = ring + j;
+            let k2 = next_ring + j;
+            // The rings at the poles collapse to a point, so they only need one triangle per sector
+            if i != 0 {
+                indices.extend([k1, k2, k1 + 1]);
+            }
+            if i != stack_count - 1 {
+                indices.extend([k1 + 1, k2, k2 + 1]);
+            }
+        }
+    }
+
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
+
+// Makes every triangle wind in `order` when seen from the side its vertex normals point to,
+// by swapping its last 2 indices if it doesn't already.
+// Triangles with no area, or normals lying flat along them, are left alone.
+pub fn fix_winding(vertices: &[ModelVertex], indices: &mut [u32], order: WindingOrder) {
+    for triangle in indices.chunks_exact_mut(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| vertices[i as usize]);
+        let position = |vertex: ModelVertex| cgmath::Vector3::from(vertex.position);
+        // Counter-clockwise triangles have a face normal pointing at the viewer
+        let face_normal = (position(b) - position(a)).cross(position(c) - position(a));
+        let vertex_normal = cgmath::Vector3::from(a.normal)
+            + cgmath::Vector3::from(b.normal)
+            + cgmath::Vector3::from(c.normal);
+        let facing = face_normal.dot(vertex_normal);
+        let is_wrong = match order {
+            WindingOrder::CounterClockwise => facing < 0.0,
+            WindingOrder::Clockwise => facing > 0.0,
+        };
+        if is_wrong {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_indices_stay_in_bounds() {
+        for (sector_count, stack_count) in [(3, 2), (4, 3), (8, 8), (16, 5), (36, 18), (0, 0)] {
+            let (vertices, indices) =
+                generate_sphere(1.0, sector_count, stack_count, WindingOrder::default());
+            let max = indices.iter().max().copied().unwrap();
+            assert!(
+                (max as usize) < vertices.len(),
+                "{sector_count}x{stack_count}: index {max} with {} vertices",
+                vertices.len()
+            );
+        }
+    }
+
+    #[test]
+    fn fix_winding_turns_flipped_faces_outwards() {
+        let (vertices, mut indices) = generate_sphere(1.0, 12, 8, WindingOrder::CounterClockwise);
+        // Flip every third triangle so they face into the sphere
+        for triangle in indices.chunks_exact_mut(3).step_by(3) {
+            triangle.swap(1, 2);
+        }
+        fix_winding(&vertices, &mut indices, WindingOrder::CounterClockwise);
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|i| cgmath::Vector3::from(vertices[i as usize].position));
+            let face_normal = (b - a).cross(c - a);
+            let centroid = (a + b + c) / 3.0;
+            assert!(
+                face_normal.dot(centroid) > 0.0,
+                "triangle {triangle:?} faces inwards"
+            );
+        }
+    }
+
+    #[test]
+    fn uv_fix_seams_after_projecting() {
+        // A torus has no poles, where U legitimately jumps around within a triangle
+        let (mut vertices, mut indices) =
+            torus::generate_torus(1.0, 0.25, 24, 8, WindingOrder::default());
+        let max_u_span = |vertices: &[ModelVertex], indices: &[u32]| {
+            indices
+                .chunks_exact(3)
+                .map(|triangle| {
+                    let us = triangle.iter().map(|&i| vertices[i as usize].tex_coords[0]);
+                    us.clone().fold(f32::NEG_INFINITY, f32::max) - us.fold(f32::INFINITY, f32::min)
+                })
+                .fold(0.0, f32::max)
+        };
+
+        uv_project_cylindrical(&mut vertices, 1.0);
+        uv_fix_seams(&mut vertices, &mut indices);
+        assert!(max_u_span(&vertices, &indices) < 0.1);
+        uv_project_spherical(&mut vertices);
+        uv_fix_seams(&mut vertices, &mut indices);
+        assert!(max_u_span(&vertices, &indices) < 0.1);
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+    }
+}
@@ -0,0 +1,67 @@
+use super::{fix_winding, vertex, WindingOrder};
+use crate::model::{compute_tangents, ModelVertex};
+
+// A flat, `size` wide square on the XZ plane facing up, centered on the origin and split into
+// `subdivisions_x` by `subdivisions_z` tiles, e.g. for a ground plane.
+// UVs go from 0 to 1 across the whole grid. With `tile_uv` every tile gets its own 0 to 1 instead,
+// so a texture repeats once per tile. Tiles don't share vertices then,
+// so this works with samplers that clamp instead of repeat.
+pub fn generate_grid(
+    size: f32,
+    subdivisions_x: u32,
+    subdivisions_z: u32,
+    tile_uv: bool,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let subdivisions_x = subdivisions_x.max(1);
+    let subdivisions_z = subdivisions_z.max(1);
+    let position = |i: u32, j: u32| {
+        [
+            (i as f32 / subdivisions_x as f32 - 0.5) * size,
+            0.0,
+            (j as f32 / subdivisions_z as f32 - 0.5) * size,
+        ]
+    };
+    let whole_uv = |i: u32, j: u32| {
+        [
+            i as f32 / subdivisions_x as f32,
+            j as f32 / subdivisions_z as f32,
+        ]
+    };
+    let normal = [0.0, 1.0, 0.0];
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity((subdivisions_x * subdivisions_z * 6) as usize);
+    if tile_uv {
+        for j in 0..subdivisions_z {
+            for i in 0..subdivisions_x {
+                let start = vertices.len() as u32;
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    vertices.push(vertex(
+                        position(i + di, j + dj),
+                        normal,
+                        [di as f32, dj as f32],
+                    ));
+                }
+                indices.extend([start, start + 2, start + 1, start + 1, start + 2, start + 3]);
+            }
+        }
+    } else {
+        for j in 0..=subdivisions_z {
+            for i in 0..=subdivisions_x {
+                vertices.push(vertex(position(i, j), normal, whole_uv(i, j)));
+            }
+        }
+        for j in 0..subdivisions_z {
+            for i in 0..subdivisions_x {
+                let k1 = j * (subdivisions_x + 1) + i;
+                let k2 = k1 + subdivisions_x + 1;
+                indices.extend([k1, k2, k1 + 1, k1 + 1, k2, k2 + 1]);
+            }
+        }
+    }
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
@@ -0,0 +1,73 @@
+use std::f32::consts::PI;
+
+use super::cylinder::ring_direction;
+use super::{fix_winding, vertex, WindingOrder};
+use crate::model::{compute_tangents, ModelVertex};
+
+// A donut lying on the XZ plane around the origin. `major_radius` goes from the middle to the
+// center of the tube and `minor_radius` is the tube's own radius.
+// U goes around the ring and V around the tube. The first and last row and column are
+// duplicated on the seams so both can go all the way from 0 to 1.
+pub fn generate_torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+    winding: WindingOrder,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let major_segments = major_segments.max(3);
+    let minor_segments = minor_segments.max(3);
+
+    let mut vertices = Vec::with_capacity(((major_segments + 1) * (minor_segments + 1)) as usize);
+    for i in 0..=major_segments {
+        let [x, _, z] = ring_direction(i, major_segments);
+        for j in 0..=minor_segments {
+            // Around the tube, starting on the outside
+            let tube_angle = j as f32 * 2.0 * PI / minor_segments as f32;
+            let normal = [x * tube_angle.cos(), tube_angle.sin(), z * tube_angle.cos()];
+            let position = [
+                x * major_radius + normal[0] * minor_radius,
+                normal[1] * minor_radius,
+                z * major_radius + normal[2] * minor_radius,
+            ];
+            vertices.push(vertex(
+                position,
+                normal,
+                [
+                    i as f32 / major_segments as f32,
+                    j as f32 / minor_segments as f32,
+                ],
+            ));
+        }
+    }
+
+    // Two triangles for every segment of the tube
+    let mut indices = Vec::with_capacity((major_segments * minor_segments * 6) as usize);
+    for i in 0..major_segments {
+        let ring = i * (minor_segments + 1);
+        let next_ring = ring + minor_segments + 1;
+        for j in 0..minor_segments {
+            let (k1, k2) = (ring + j, next_ring + j);
+            indices.extend([k1, k2, k1 + 1, k1 + 1, k2, k2 + 1]);
+        }
+    }
+
+    compute_tangents(&mut vertices, &indices);
+    fix_winding(&vertices, &mut indices, winding);
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_count_matches_segments() {
+        for (major, minor) in [(3, 3), (8, 4), (24, 12), (48, 16)] {
+            let (vertices, indices) =
+                generate_torus(1.0, 0.25, major, minor, WindingOrder::default());
+            assert_eq!(indices.len(), (major * minor * 6) as usize);
+            assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+
+// Keeps the last few frame times around so we can look at averages instead of single spikes
+pub struct FrameHistory {
+    // In milliseconds, oldest first
+    frame_times: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl FrameHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, frame_time_ms: f32) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time_ms);
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_times.clear();
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.frame_times.len() == self.capacity
+    }
+
+    pub fn average(&self) -> Option<f32> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        Some(self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32)
+    }
+}
+
+// Smallest fraction of the window size we'll render at
+const MIN_RENDER_SCALE: f32 = 0.5;
+const MAX_RENDER_SCALE: f32 = 1.0;
+// How much the scale changes in one step
+const RENDER_SCALE_STEP: f32 = 0.05;
+// Frames averaged before each decision
+const SCALER_WINDOW: usize = 60;
+
+// Lowers the resolution we render at when frames take too long, and raises it again
+// once there's headroom. The scene is rendered at `render_scale` times the window
+// size, then stretched back up to fill the window.
+pub struct DynamicResolutionScaler {
+    pub target_ms: f32,
+    pub render_scale: f32,
+    history: FrameHistory,
+}
+
+impl DynamicResolutionScaler {
+    pub fn new(target_ms: f32) -> Self {
+        Self {
+            target_ms,
+            render_scale: MAX_RENDER_SCALE,
+            history: FrameHistory::new(SCALER_WINDOW),
+        }
+    }
+
+    // Records a frame and returns `true` if the render scale changed
+    pub fn update(&mut self, frame_time_ms: f32) -> bool {
+        self.history.push(frame_time_ms);
+        // Only decide once we have a full window of frames, otherwise one slow frame
+        // (or the frames right after a change) would make the scale bounce around
+        if !self.history.is_full() {
+            return false;
+        }
+        let average = match self.history.average() {
+            Some(average) => average,
+            None => return false,
+        };
+
+        let old_scale = self.render_scale;
+        if average > self.target_ms * 1.2 {
+            self.render_scale = (self.render_scale - RENDER_SCALE_STEP).max(MIN_RENDER_SCALE);
+        } else if average < self.target_ms * 0.8 {
+            self.render_scale = (self.render_scale + RENDER_SCALE_STEP).min(MAX_RENDER_SCALE);
+        }
+
+        // Start measuring again at the new resolution
+        self.history.clear();
+        self.render_scale != old_scale
+    }
+
+    // Size of the offscreen target for a window of `width` x `height`
+    pub fn render_size(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            ((width as f32 * self.render_scale) as u32).max(1),
+            ((height as f32 * self.render_scale) as u32).max(1),
+        )
+    }
+}
+
+// Milliseconds from a fixed starting point, monotonic on every platform.
+// On the web this is `performance.now()`, which has sub-millisecond resolution
+// unlike `Date.now()` (what `Instant` can end up using in the browser).
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    static START: OnceLock<instant::Instant> = OnceLock::new();
+    START
+        .get_or_init(instant::Instant::now)
+        .elapsed()
+        .as_secs_f64()
+        * 1000.0
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|win| win.performance())
+        .map(|performance| performance.now())
+        .unwrap_or(0.0)
+}
+
+// Measures the time between updates
+pub struct FrameClock {
+    last_ms: f64,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self { last_ms: now_ms() }
+    }
+
+    // Seconds since the last call
+    pub fn tick(&mut self) -> f32 {
+        let now = now_ms();
+        // Clamp in case the browser hands us an older timestamp (e.g. after a tab switch)
+        let dt = ((now - self.last_ms) / 1000.0).max(0.0) as f32;
+        self.last_ms = now;
+        dt
+    }
+}
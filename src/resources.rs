@@ -1,7 +1,16 @@
-use std::io::{BufReader, Cursor};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
 
 use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
 
 use crate::{model, texture};
 
@@ -18,6 +27,12 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
+// Where an asset lives on disk for native builds
+#[cfg(not(target_arch = "wasm32"))]
+pub fn asset_path(file_name: &str) -> PathBuf {
+    Path::new("assets").join(file_name)
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -32,9 +47,7 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
             log::warn!("{}", txt);
 
         } else {
-            let path = std::path::Path::new("assets")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+            let txt = std::fs::read_to_string(asset_path(file_name))?;
         }
     }
 
@@ -51,16 +64,103 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
                 .await?
                 .to_vec();
         } else {
-            let path = std::path::Path::new("assets")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+            let data = std::fs::read(asset_path(file_name))?;
         }
     }
 
     Ok(data)
 }
 
-pub async fn load_texture(
+// Same as `load_binary`, reporting how much of the file has arrived (0.0 - 1.0).
+// On the web the response is read in chunks as they come in. Servers that don't
+// send a `Content-Length` only report once it's done.
+pub async fn load_binary_with_progress(
+    file_name: &str,
+    on_progress: &dyn Fn(f32),
+) -> anyhow::Result<Vec<u8>> {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            use futures::StreamExt;
+
+            let url = format_url(file_name);
+            let response = reqwest::get(url).await?;
+            let total = response.content_length();
+            let mut data = Vec::with_capacity(total.unwrap_or(0) as usize);
+            let mut chunks = response.bytes_stream();
+            while let Some(chunk) = chunks.next().await {
+                data.extend_from_slice(&chunk?);
+                if let Some(total) = total.filter(|&total| total > 0) {
+                    on_progress((data.len() as f32 / total as f32).min(1.0));
+                }
+            }
+        } else {
+            let data = std::fs::read(asset_path(file_name))?;
+        }
+    }
+
+    on_progress(1.0);
+    Ok(data)
+}
+
+// Maps a loading step's own 0.0 - 1.0 progress into `start..end` of the overall progress
+fn progress_range(on_progress: &dyn Fn(f32), start: f32, end: f32) -> impl Fn(f32) + '_ {
+    move |progress| on_progress(start + (end - start) * progress.clamp(0.0, 1.0))
+}
+
+// Reports how far through the text a parser has read, by bytes consumed.
+// Only reports every whole percent so big files don't call back for every line.
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: usize,
+    total: usize,
+    last_percent: usize,
+    on_progress: &'a dyn Fn(f32),
+}
+
+impl<'a, R: BufRead> ProgressReader<'a, R> {
+    fn new(inner: R, total: usize, on_progress: &'a dyn Fn(f32)) -> Self {
+        Self {
+            inner,
+            read: 0,
+            total: total.max(1),
+            last_percent: 0,
+            on_progress,
+        }
+    }
+}
+
+impl<'a, R: BufRead> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.consume_progress(amount);
+        Ok(amount)
+    }
+}
+
+impl<'a, R: BufRead> BufRead for ProgressReader<'a, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount);
+        self.consume_progress(amount);
+    }
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    fn consume_progress(&mut self, amount: usize) {
+        self.read = (self.read + amount).min(self.total);
+        let percent = self.read * 100 / self.total;
+        if percent > self.last_percent {
+            self.last_percent = percent;
+            (self.on_progress)(percent as f32 / 100.0);
+        }
+    }
+}
+
+// For textures that hold colors (diffuse / albedo, emissive, lightmaps)
+pub async fn load_texture_srgb(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
@@ -69,15 +169,93 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name)
 }
 
+// For textures that hold data (normal maps, roughness / metallic, specular exponent).
+// Loading these as sRGB would gamma expand the values and throw off the lighting.
+pub async fn load_texture_linear(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let data = load_binary(file_name).await?;
+    texture::Texture::from_bytes_linear(device, queue, &data, file_name)
+}
+
+// Uses the MTL's `norm` map if there is one, otherwise looks for a `<diffuse>_normal.png`
+// next to the diffuse texture. Materials without either get a flat normal.
+async fn load_normal_texture(
+    material: &tobj::Material,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Option<texture::Texture> {
+    if !material.normal_texture.is_empty() {
+        return match load_texture_linear(&material.normal_texture, device, queue).await {
+            Ok(texture) => Some(texture),
+            Err(e) => {
+                log::warn!("Couldn't load {}: {}", material.normal_texture, e);
+                None
+            }
+        };
+    }
+    let diffuse_path = std::path::Path::new(&material.diffuse_texture);
+    let stem = diffuse_path.file_stem()?.to_str()?;
+    let normal_path = diffuse_path.with_file_name(format!("{}_normal.png", stem));
+    // Most textures won't have one, so a missing file isn't worth a warning
+    load_texture_linear(normal_path.to_str()?, device, queue)
+        .await
+        .ok()
+}
+
+// Picks the loader from the file extension, OBJ unless it's `.gltf` / `.glb`
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+    load_model_with_progress(file_name, device, queue, layout, |_| {}).await
+}
+
+// Same as `load_model`, calling `on_progress` with how far along it is (0.0 - 1.0),
+// e.g. to draw a loading bar. The values only go up and the last one is always 1.0.
+// Roughly the first 20% is fetching the file, the rest is parsing it and
+// creating the materials and meshes.
+pub async fn load_model_with_progress<F: Fn(f32)>(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    on_progress: F,
+) -> anyhow::Result<model::Model> {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf" | "glb") => {
+            load_gltf_with_progress(file_name, device, queue, layout, &on_progress).await
+        }
+        _ => load_obj_with_progress(file_name, device, queue, layout, &on_progress).await,
+    }
+}
+
+// Fetching is 0 - 20%, parsing the OBJ text 20 - 60% (by how many bytes were read),
+// then every material and mesh gets an equal share of the rest
+async fn load_obj_with_progress(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    on_progress: &dyn Fn(f32),
+) -> anyhow::Result<model::Model> {
+    let obj_data =
+        load_binary_with_progress(file_name, &progress_range(on_progress, 0.0, 0.2)).await?;
+    let obj_text = String::from_utf8(obj_data)?;
+    let parse_progress = progress_range(on_progress, 0.2, 0.6);
+    let mut obj_reader = ProgressReader::new(
+        BufReader::new(Cursor::new(obj_text.as_bytes())),
+        obj_text.len(),
+        &parse_progress,
+    );
 
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
@@ -92,71 +270,692 @@ pub async fn load_model(
         },
     )
     .await?;
+    let obj_materials = obj_materials?;
+
+    let built_progress = progress_range(on_progress, 0.6, 1.0);
+    let total = (obj_materials.len() + models.len()).max(1);
+    let mut built = 0;
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    for m in obj_materials {
+        // `map_Kd` is a color. Data maps like `map_bump` / `map_Ns` go through `load_texture_linear`
+        // Materials without a `map_Kd` get a checkerboard so they stand out
+        let diffuse_texture = if m.diffuse_texture.is_empty() {
+            texture::Texture::create_checkerboard(
+                device,
+                queue,
+                64,
+                [255, 0, 255, 255],
+                [0, 0, 0, 255],
+                "Missing Texture",
+            )
+        } else {
+            load_texture_srgb(&m.diffuse_texture, device, queue).await?
+        };
+        // MTL has no lightmap slot, so we use a custom `map_Lightmap` line
+        let lightmap_texture = match m.unknown_param.get("map_Lightmap") {
+            Some(path) => Some(load_texture_srgb(path, device, queue).await?),
+            None => None,
+        };
+        let normal_texture = load_normal_texture(&m, device, queue).await;
+        // `map_Ks`, which only has to say where highlights go, so it's treated as data
+        let specular_texture = if m.specular_texture.is_empty() {
+            None
+        } else {
+            Some(load_texture_linear(&m.specular_texture, device, queue).await?)
+        };
+        let mut material = model::Material::new(
+            device,
+            queue,
+            &m.name,
+            diffuse_texture,
+            &m.diffuse_texture,
+            lightmap_texture,
+            normal_texture,
+            specular_texture,
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+        );
+        // `Ns` from the MTL, 0 when it's missing
+        if m.shininess > 0.0 {
+            material.set_shininess(queue, m.shininess);
+        }
+        materials.push(material);
+        built += 1;
+        built_progress(built as f32 / total as f32);
+    }
 
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
+    let mut meshes = Vec::new();
+    for m in models {
+        let mut vertices = (0..m.mesh.positions.len() / 3)
+            .map(|i| model::ModelVertex {
+                position: [
+                    m.mesh.positions[i * 3],
+                    m.mesh.positions[i * 3 + 1],
+                    m.mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+                normal: [
+                    m.mesh.normals[i * 3],
+                    m.mesh.normals[i * 3 + 1],
+                    m.mesh.normals[i * 3 + 2],
+                ],
+                // OBJ only has one UV set (tobj skips anything else),
+                // so lightmaps have to be unwrapped to the same UVs
+                tex_coords2: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+                // Vertices are shared between triangles, so these get filled in
+                // by `add_barycentric_coordinates` when a wireframe is needed
+                barycentric: [0.0; 3],
+                tangent: [0.0; 3],
+            })
+            .collect::<Vec<_>>();
+        model::compute_tangents(&mut vertices, &m.mesh.indices);
+
+        meshes.push(model::Mesh::new(
+            device,
+            file_name,
+            vertices,
+            m.mesh.indices,
+            m.mesh.material_id.unwrap_or(0),
+        ));
+        built += 1;
+        built_progress(built as f32 / total as f32);
+    }
+
+    on_progress(1.0);
+    Ok(model::Model { meshes, materials })
+}
+
+// One material per image, all sampling their diffuse color from `atlas` instead of having
+// a texture each, so they can share it. Loads every image before uploading the atlas once.
+// Only the diffuse color comes from the image, the other maps get their flat fallbacks.
+pub async fn load_atlas_materials(
+    file_names: &[&str],
+    atlas: &mut texture::TextureAtlas,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Vec<model::Material>> {
+    let mut rects = Vec::with_capacity(file_names.len());
+    for file_name in file_names {
+        let data = load_binary(file_name).await?;
+        rects.push(atlas.add(&data, file_name)?);
+    }
+    let atlas_texture = atlas.upload(device, queue);
+
+    Ok(file_names
+        .iter()
+        .zip(rects)
+        .map(|(file_name, rect)| {
+            // Never bound, `use_atlas` swaps the atlas in
+            let diffuse_texture =
+                texture::Texture::from_color(device, queue, [255, 255, 255, 255], file_name);
+            let mut material = model::Material::new(
+                device,
+                queue,
+                file_name,
+                diffuse_texture,
+                file_name,
+                None,
+                None,
+                None,
+                layout,
+            );
+            material.use_atlas(device, queue, atlas_texture, rect, layout);
+            material
         })
+        .collect())
+}
+
+// glTF points at other files relative to itself, we load everything relative to the assets folder
+fn sibling_path(file_name: &str, uri: &str) -> String {
+    match std::path::Path::new(file_name).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            format!("{}/{}", parent.to_string_lossy(), uri)
+        }
+        _ => uri.to_string(),
+    }
+}
+
+// Encoded image bytes (PNG / JPEG) for a glTF texture, either from a buffer or a separate file.
+// Also returns the file's path, which is empty when the image is inside the glTF.
+async fn load_gltf_image(
+    file_name: &str,
+    texture: &gltf::Texture<'_>,
+    buffers: &[Vec<u8>],
+) -> anyhow::Result<(Vec<u8>, String)> {
+    match texture.source().source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            let bytes = buffer[view.offset()..view.offset() + view.length()].to_vec();
+            Ok((bytes, String::new()))
+        }
+        gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+            anyhow::bail!("{} embeds a base64 image, which isn't supported", file_name)
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            let path = sibling_path(file_name, uri);
+            Ok((load_binary(&path).await?, path))
+        }
     }
+}
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+// Loads a `.gltf` (with its `.bin` and images next to it) or a self contained `.glb`.
+// Every primitive of every mesh becomes a `Mesh`, node transforms aren't applied,
+// same as the OBJ loader ignores anything but the raw vertices, `load_scene_gltf` keeps them.
+// Only the base color and normal textures are used, the rest of the PBR material is skipped.
+async fn load_gltf_with_progress(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    on_progress: &dyn Fn(f32),
+) -> anyhow::Result<model::Model> {
+    let (_, model, _) = load_gltf_document(file_name, device, queue, layout, on_progress).await?;
+    Ok(model)
+}
+
+// Loads the default scene of a glTF (or the first one if it doesn't say) as a tree of nodes,
+// with every node's transform multiplied down from its parents.
+// The meshes and materials are loaded the same way as `load_model` does for a glTF,
+// nodes using the same glTF mesh share its `Mesh`es.
+pub async fn load_scene_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Scene> {
+    let (document, model, mesh_ranges) =
+        load_gltf_document(file_name, device, queue, layout, &|_| {}).await?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow::anyhow!("{} doesn't have a scene", file_name))?;
+
+    // Depth first, so parents always come before their children
+    let mut nodes: Vec<model::Node> = Vec::new();
+    let mut parents = Vec::new();
+    let mut stack: Vec<(gltf::Node, Option<usize>)> =
+        scene.nodes().map(|node| (node, None)).collect();
+    stack.reverse();
+    while let Some((node, parent)) = stack.pop() {
+        let index = nodes.len();
+        let meshes = node
+            .mesh()
+            .map(|mesh| mesh_ranges[mesh.index()].clone())
+            .unwrap_or_default();
+        let mut scene_node = model::Node::new(node.name().unwrap_or_default().to_string(), meshes);
+        let (position, [x, y, z, w], scale) = node.transform().decomposed();
+        scene_node.set_position(position.into());
+        scene_node.set_rotation(cgmath::Quaternion::new(w, x, y, z));
+        scene_node.set_scale(scale.into());
+        nodes.push(scene_node);
+        if let Some(parent) = parent {
+            parents.push((index, parent));
+        }
+        let first_child = stack.len();
+        stack.extend(node.children().map(|child| (child, Some(index))));
+        stack[first_child..].reverse();
+    }
+
+    let mut scene = model::Scene { model, nodes };
+    for (child, parent) in parents {
+        scene.set_parent(child, parent);
+    }
+    scene.compute_world_transforms();
+    Ok(scene)
+}
+
+// Shared by `load_gltf_with_progress` and `load_scene_gltf`. Besides the model, returns the
+// parsed document and which of the model's meshes each glTF mesh turned into, by glTF mesh.
+// Fetching is 0 - 20%, the buffers it points to 20 - 40%,
+// then every material and primitive gets an equal share of the rest
+async fn load_gltf_document(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    on_progress: &dyn Fn(f32),
+) -> anyhow::Result<(gltf::Document, model::Model, Vec<Range<usize>>)> {
+    let data = load_binary_with_progress(file_name, &progress_range(on_progress, 0.0, 0.2)).await?;
+    let gltf = gltf::Gltf::from_slice(&data)?;
+    let document = &gltf.document;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            // The binary chunk of a `.glb`
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("{} has no binary chunk", file_name))?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                anyhow::bail!(
+                    "{} embeds a base64 buffer, which isn't supported",
+                    file_name
+                )
+            }
+            gltf::buffer::Source::Uri(uri) => load_binary(&sibling_path(file_name, uri)).await?,
+        };
+        buffers.push(data);
+        on_progress(0.2 + 0.2 * buffers.len() as f32 / document.buffers().len() as f32);
+    }
+
+    let built_progress = progress_range(on_progress, 0.4, 1.0);
+    let primitive_count = document
+        .meshes()
+        .map(|mesh| mesh.primitives().len())
+        .sum::<usize>();
+    let total = (document.materials().len() + primitive_count).max(1);
+    let mut built = 0;
+
+    let mut materials = Vec::new();
+    for m in document.materials() {
+        let name = m.name().unwrap_or("glTF Material");
+        let (diffuse_texture, diffuse_path) = match m.pbr_metallic_roughness().base_color_texture()
+        {
+            Some(info) => {
+                let (bytes, path) = load_gltf_image(file_name, &info.texture(), &buffers).await?;
+                (
+                    texture::Texture::from_bytes(device, queue, &bytes, name)?,
+                    path,
+                )
+            }
+            // Untextured materials only have a color factor, which is linear
+            None => {
+                let [r, g, b, a] = m.pbr_metallic_roughness().base_color_factor();
+                let to_byte = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let to_srgb = |channel: f32| to_byte(channel.clamp(0.0, 1.0).powf(1.0 / 2.2));
+                let color = [to_srgb(r), to_srgb(g), to_srgb(b), to_byte(a)];
+                (
+                    texture::Texture::from_color(device, queue, color, name),
+                    String::new(),
+                )
+            }
+        };
+        let normal_texture = match m.normal_texture() {
+            Some(info) => {
+                let (bytes, _) = load_gltf_image(file_name, &info.texture(), &buffers).await?;
+                Some(texture::Texture::from_bytes_linear(
+                    device, queue, &bytes, name,
+                )?)
+            }
+            None => None,
+        };
+        let mut material = model::Material::new(
+            device,
+            queue,
+            name,
+            diffuse_texture,
+            // Empty for textures inside the glTF, those can't be hot reloaded
+            &diffuse_path,
+            None,
+            normal_texture,
+            None,
+            layout,
+        );
+        // Only the metallic and roughness factors. The metallic / roughness, occlusion and
+        // emissive textures are skipped, the material bind group has no slots for them.
+        let pbr = m.pbr_metallic_roughness();
+        material.set_pbr(queue, pbr.metallic_factor(), pbr.roughness_factor(), 1.0);
+        materials.push(material);
+        built += 1;
+        built_progress(built as f32 / total as f32);
+    }
+    // Primitives without a material use glTF's default one, which is plain white
+    let default_material = materials.len();
+    let mut needs_default_material = false;
+
+    let mut meshes = Vec::new();
+    let mut mesh_ranges = Vec::with_capacity(document.meshes().len());
+    for mesh in document.meshes() {
+        let name = mesh.name().unwrap_or(file_name);
+        let first_mesh = meshes.len();
+        for primitive in mesh.primitives() {
+            built += 1;
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!("Skipping {} primitive in {}", name, file_name);
+                built_progress(built as f32 / total as f32);
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| anyhow::anyhow!("{} in {} has no positions", name, file_name))?;
+            let mut normals = reader
+                .read_normals()
+                .ok_or_else(|| anyhow::anyhow!("{} in {} has no normals", name, file_name))?;
+            let mut tex_coords = reader
+                .read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32());
+            // A second UV set is usually the lightmap unwrap
+            let mut tex_coords2 = reader
+                .read_tex_coords(1)
+                .map(|tex_coords| tex_coords.into_f32());
+
+            let mut vertices = positions
+                .map(|position| {
+                    let uv = tex_coords
+                        .as_mut()
+                        .and_then(|tex_coords| tex_coords.next())
+                        .unwrap_or([0.0; 2]);
+                    model::ModelVertex {
+                        position,
+                        tex_coords: uv,
+                        normal: normals.next().unwrap_or([0.0, 1.0, 0.0]),
+                        tex_coords2: tex_coords2
+                            .as_mut()
+                            .and_then(|tex_coords| tex_coords.next())
+                            .unwrap_or(uv),
+                        barycentric: [0.0; 3],
+                        tangent: [0.0; 3],
+                    }
                 })
                 .collect::<Vec<_>>();
+            // Indices are optional, without them every 3 vertices make a triangle
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            model::compute_tangents(&mut vertices, &indices);
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+            let material = primitive.material().index().unwrap_or_else(|| {
+                needs_default_material = true;
+                default_material
             });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+            meshes.push(model::Mesh::new(device, name, vertices, indices, material));
+            built_progress(built as f32 / total as f32);
+        }
+        mesh_ranges.push(first_mesh..meshes.len());
+    }
+
+    if needs_default_material {
+        let diffuse_texture =
+            texture::Texture::from_color(device, queue, [255, 255, 255, 255], "Default Material");
+        materials.push(model::Material::new(
+            device,
+            queue,
+            "Default Material",
+            diffuse_texture,
+            "",
+            None,
+            None,
+            None,
+            layout,
+        ));
+    }
+
+    on_progress(1.0);
+    Ok((
+        gltf.document,
+        model::Model { meshes, materials },
+        mesh_ranges,
+    ))
+}
+
+// Hands out shared copies of models that were already loaded, so loading the same file
+// twice doesn't parse it and upload its buffers again. Keyed by file name.
+// `load_model` stays available for when you want a copy of your own (e.g. to edit materials).
+#[derive(Default)]
+pub struct ModelCache {
+    models: HashMap<String, Arc<model::Model>>,
+}
+
+impl ModelCache {
+    // Hands `model` over to the cache, replacing anything already loaded under that name
+    pub fn insert(&mut self, file_name: &str, model: model::Model) -> Arc<model::Model> {
+        let model = Arc::new(model);
+        self.models.insert(file_name.to_string(), model.clone());
+        model
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<Arc<model::Model>> {
+        self.models.get(file_name).cloned()
+    }
+
+    // Changes a model that came from the cache in place (e.g. to reload its textures),
+    // the cache keeps handing out the edited version. Only works while `model` is the
+    // one copy outside the cache, otherwise nothing changes and this returns false.
+    pub fn edit(
+        &mut self,
+        file_name: &str,
+        model: &mut Arc<model::Model>,
+        edit: impl FnOnce(&mut model::Model),
+    ) -> bool {
+        // Let go of our own copy while editing, anything else under that name stays put
+        let cached = self.models.remove(file_name);
+        let is_ours = cached
+            .as_ref()
+            .is_some_and(|cached| Arc::ptr_eq(cached, model));
+        let other = if is_ours { None } else { cached };
+
+        let edited = match Arc::get_mut(model) {
+            Some(model) => {
+                edit(model);
+                true
+            }
+            None => false,
+        };
+
+        if let Some(other) = other {
+            self.models.insert(file_name.to_string(), other);
+        } else if is_ours {
+            self.models.insert(file_name.to_string(), model.clone());
+        }
+        edited
+    }
+}
+
+// One cache for the whole app. Don't keep it locked across frames,
+// load what you need and let the guard go.
+pub fn model_cache() -> &'static Mutex<ModelCache> {
+    static CACHE: OnceLock<Mutex<ModelCache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+// Reported by `AsyncAssetQueue` as each model finishes, e.g. to drive a loading bar
+#[derive(Debug)]
+pub enum AssetEvent {
+    Loaded {
+        file_name: String,
+        loaded: usize,
+        total: usize,
+    },
+    Failed {
+        file_name: String,
+        error: String,
+        loaded: usize,
+        total: usize,
+    },
+}
+
+struct AssetRequest {
+    file_name: String,
+    priority: i32,
+}
+
+// Loads a batch of models concurrently instead of awaiting them one by one.
+// On the web this means all the requests are in flight at the same time.
+#[derive(Default)]
+pub struct AsyncAssetQueue {
+    requests: Vec<AssetRequest>,
+}
+
+impl AsyncAssetQueue {
+    // Higher priorities get started first (e.g. objects the camera can see)
+    pub fn push(&mut self, file_name: &str, priority: i32) {
+        self.requests.push(AssetRequest {
+            file_name: file_name.to_string(),
+            priority,
+        });
+    }
+
+    // Resolves once everything is loaded. Results are in the same order the models were pushed.
+    // Models go through `model_cache`, so anything already loaded is handed out straight away
+    // and a file pushed more than once is only read once.
+    pub async fn load_all(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        on_event: impl FnMut(AssetEvent),
+    ) -> Vec<anyhow::Result<Arc<model::Model>>> {
+        let mut order = (0..self.requests.len()).collect::<Vec<_>>();
+        // Stable sort so equal priorities keep the order they were pushed in
+        order.sort_by_key(|&index| std::cmp::Reverse(self.requests[index].priority));
+
+        // Not locked while loading, so other code can use the cache in the meantime
+        let mut cached = Vec::new();
+        let mut missing = Vec::new();
+        for index in order {
+            let file_name = self.requests[index].file_name.as_str();
+            if cached.contains(&file_name) || missing.contains(&file_name) {
+                continue;
+            }
+            if model_cache().lock().unwrap().get(file_name).is_some() {
+                cached.push(file_name);
+            } else {
+                missing.push(file_name);
+            }
+        }
+
+        // Events count files rather than requests, duplicates don't show up twice
+        let total = cached.len() + missing.len();
+        let on_event = RefCell::new(on_event);
+        let loaded = Cell::new(0);
+        for file_name in cached {
+            loaded.set(loaded.get() + 1);
+            (on_event.borrow_mut())(AssetEvent::Loaded {
+                file_name: file_name.to_string(),
+                loaded: loaded.get(),
+                total,
             });
+        }
+
+        // Every future runs on the same thread, so they can share the callback
+        let loaders = missing.into_iter().map(|file_name| {
+            let on_event = &on_event;
+            let loaded = &loaded;
+            async move {
+                let result = load_model(file_name, device, queue, layout).await;
+                loaded.set(loaded.get() + 1);
+                let event = match &result {
+                    Ok(_) => AssetEvent::Loaded {
+                        file_name: file_name.to_string(),
+                        loaded: loaded.get(),
+                        total,
+                    },
+                    Err(e) => AssetEvent::Failed {
+                        file_name: file_name.to_string(),
+                        error: e.to_string(),
+                        loaded: loaded.get(),
+                        total,
+                    },
+                };
+                (on_event.borrow_mut())(event);
+                (file_name, result)
+            }
+        });
 
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+        let results = futures::future::join_all(loaders).await;
+        let mut cache = model_cache().lock().unwrap();
+        let mut errors = HashMap::new();
+        for (file_name, result) in results {
+            match result {
+                Ok(model) => {
+                    cache.insert(file_name, model);
+                }
+                Err(e) => {
+                    errors.insert(file_name, e.to_string());
+                }
             }
+        }
+
+        self.requests
+            .iter()
+            .map(|request| {
+                let file_name = request.file_name.as_str();
+                cache.get(file_name).ok_or_else(|| {
+                    let error = errors
+                        .get(file_name)
+                        .map(String::as_str)
+                        .unwrap_or("it's missing from the model cache");
+                    anyhow::anyhow!("Couldn't load {}: {}", file_name, error)
+                })
+            })
+            .collect()
+    }
+}
+
+// Watches the assets folder and reports any files that changed since the last poll,
+// so textures can be re-uploaded without restarting the app.
+// Only available on native - there's no file system to watch on the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct HotReloader {
+    // Dropping the watcher stops it, so we keep it around
+    _watcher: notify::RecommendedWatcher,
+    receiver: mpsc::Receiver<PathBuf>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HotReloader {
+    pub fn new() -> anyhow::Result<Self> {
+        use notify::Watcher;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res {
+                    if event.kind.is_modify() || event.kind.is_create() {
+                        for path in event.paths {
+                            // The receiver only goes away when the app closes
+                            let _ = sender.send(path);
+                        }
+                    }
+                }
+            })?;
+        watcher.watch(Path::new("assets"), notify::RecursiveMode::Recursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            receiver,
         })
-        .collect::<Vec<_>>();
+    }
 
-    Ok(model::Model { meshes, materials })
+    // Drain the change events. Editors often write a file several times per save,
+    // so we only return each path once.
+    pub fn changed_files(&self) -> HashSet<PathBuf> {
+        self.receiver
+            .try_iter()
+            .filter_map(|path| path.canonicalize().ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn progress_never_goes_backwards() {
+        let reported = RefCell::new(Vec::new());
+        let on_progress = |progress: f32| reported.borrow_mut().push(progress);
+
+        // Like `load_obj_with_progress`: a download step, then parsing the text line by line
+        let text = "v 0.0 0.0 0.0\n".repeat(500);
+        let download_progress = progress_range(&on_progress, 0.0, 0.2);
+        download_progress(0.5);
+        download_progress(1.0);
+        let parse_progress = progress_range(&on_progress, 0.2, 1.0);
+        let reader = ProgressReader::new(Cursor::new(text.as_bytes()), text.len(), &parse_progress);
+        assert_eq!(reader.lines().count(), 500);
+
+        let reported = reported.take();
+        assert!(reported.len() > 2);
+        assert!(
+            reported.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{reported:?}"
+        );
+        assert_eq!(reported.last(), Some(&1.0));
+    }
 }
@@ -0,0 +1,202 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::mpsc;
+
+use anyhow::anyhow;
+
+// A frame that's been copied into a buffer on the GPU and is waiting to be read back
+pub struct Screenshot {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    // Rows in the buffer are padded out to `COPY_BYTES_PER_ROW_ALIGNMENT`
+    padded_bytes_per_row: u32,
+    // Most swapchains are BGRA, but PNGs want RGBA
+    is_bgra: bool,
+    path: String,
+    receiver: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl Screenshot {
+    // Records a copy of `texture` into a staging buffer. Call `map` once the encoder is submitted.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        path: &str,
+    ) -> anyhow::Result<Self> {
+        if format.describe().block_size != 4 {
+            return Err(anyhow!("Can't take a screenshot of a {:?} texture", format));
+        }
+        let is_bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            is_bgra,
+            path: path.to_string(),
+            receiver: None,
+        })
+    }
+
+    // Ask the GPU to map the buffer so we can read it. This finishes after the next
+    // `device.poll` on native, or whenever the browser gets to it on the web.
+    pub fn map(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // If the screenshot got dropped nobody is waiting for this anymore
+                let _ = sender.send(result);
+            });
+        self.receiver = Some(receiver);
+    }
+
+    // Saves the image if the buffer is ready.
+    // Returns `None` while we're still waiting on the GPU, otherwise the path it was saved to.
+    pub fn try_save(&self) -> Option<anyhow::Result<String>> {
+        let data = match self.try_read()? {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(save_png(&self.path, &data, self.width, self.height).map(|_| self.path.clone()))
+    }
+
+    // Tightly packed RGBA8 pixels, or `None` while we're still waiting on the GPU
+    pub fn try_read(&self) -> Option<anyhow::Result<Vec<u8>>> {
+        let result = match self.receiver.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return Some(Err(anyhow!("Screenshot buffer was never mapped")))
+            }
+        };
+        if let Err(e) = result {
+            return Some(Err(anyhow!("Couldn't map screenshot buffer: {}", e)));
+        }
+
+        // Strip the row padding and swap to RGBA if needed
+        let mut data = Vec::with_capacity((self.width * self.height * 4) as usize);
+        {
+            let mapped = self.buffer.slice(..).get_mapped_range();
+            for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+                data.extend_from_slice(&row[..(self.width * 4) as usize]);
+            }
+        }
+        self.buffer.unmap();
+
+        if self.is_bgra {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Some(Ok(data))
+    }
+}
+
+// Adds the current time to the file name so we don't overwrite previous screenshots
+// e.g. `screenshot.png` -> `screenshot-1665619200.png`
+pub fn timestamped_path(path: &str) -> String {
+    let timestamp = instant::SystemTime::now()
+        .duration_since(instant::SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_else(|| "screenshot".into());
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, timestamp, extension.to_string_lossy()),
+        None => format!("{}-{}.png", stem, timestamp),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_png(path: &str, data: &[u8], width: u32, height: u32) -> anyhow::Result<()> {
+    image::save_buffer(path, data, width, height, image::ColorType::Rgba8)?;
+    Ok(())
+}
+
+// There's no file system on the web, so we hand the PNG to the browser as a download
+#[cfg(target_arch = "wasm32")]
+fn save_png(path: &str, data: &[u8], width: u32, height: u32) -> anyhow::Result<()> {
+    use image::ImageEncoder;
+    use wasm_bindgen::JsCast;
+
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png).write_image(
+        data,
+        width,
+        height,
+        image::ColorType::Rgba8,
+    )?;
+
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(png.as_slice()));
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &parts,
+        web_sys::BlobPropertyBag::new().type_("image/png"),
+    )
+    .map_err(|_| anyhow!("Couldn't create screenshot blob"))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|_| anyhow!("Couldn't create screenshot URL"))?;
+
+    let link = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .ok_or_else(|| anyhow!("Couldn't create download link"))?;
+    link.set_href(&url);
+    link.set_download(path);
+    link.click();
+
+    web_sys::Url::revoke_object_url(&url).map_err(|_| anyhow!("Couldn't revoke screenshot URL"))?;
+    Ok(())
+}
@@ -12,10 +12,39 @@ var<uniform> camera: CameraUniform;
 
 struct Light {
     position: vec3<f32>,
+    // 0 is a point light, 1 is directional, 2 is a spot light
+    light_type: u32,
     color: vec3<f32>,
+    direction: vec3<f32>,
+    // Cosines of the spot light cone angles
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+}
+// Sized by `PhongConfig::max_lights`. On the web this becomes a fixed size uniform array,
+// see `pipeline::lights_shader_source`
+struct Lights {
+    count: u32,
+    lights: array<Light>,
 }
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
+// Depth as seen from the light at `shadow.light_index`, see `ShadowPass`
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+struct Shadow {
+    light_view_proj: mat4x4<f32>,
+    // 0xffffffff when no light casts a shadow
+    light_index: u32,
+}
+@group(2) @binding(3)
+var<uniform> shadow: Shadow;
+// The same for the first spot light, with a perspective projection
+@group(2) @binding(4)
+var t_spot_shadow: texture_depth_2d;
+@group(2) @binding(5)
+var<uniform> spot_shadow: Shadow;
 
 // This is the input from the vertex buffer we created
 // We get the properties from our Vertex struct here
@@ -25,6 +54,10 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tex_coords2: vec2<f32>,
+    @location(4) barycentric: vec3<f32>,
+    // After the instance locations
+    @location(13) tangent: vec3<f32>,
 };
 // The instance buffer
 struct InstanceInput {
@@ -35,6 +68,8 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    // From a separate buffer, see `InstanceColor`
+    @location(12) color: vec4<f32>,
 }
 
 // The output we send to our fragment shader
@@ -46,6 +81,10 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) tex_coords2: vec2<f32>,
+    @location(4) barycentric: vec3<f32>,
+    @location(5) color: vec4<f32>,
+    @location(6) world_tangent: vec3<f32>,
 };
 
 @vertex
@@ -69,8 +108,12 @@ fn vs_main(
     // We define the output we want to send over to frag shader
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.tex_coords2 = model.tex_coords2;
+    out.barycentric = model.barycentric;
+    out.color = instance.color;
 
     out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = normal_matrix * model.tangent;
     var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
 
@@ -87,27 +130,234 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+// Materials without a lightmap get a white texture here, so it has no effect
+@group(0) @binding(2)
+var t_lightmap: texture_2d<f32>;
+@group(0) @binding(3)
+var s_lightmap: sampler;
+// Where the diffuse texture sits inside an atlas (the whole texture when there's no atlas)
+struct AtlasRect {
+    offset: vec2<f32>,
+    size: vec2<f32>,
+}
+struct MaterialUniform {
+    atlas_rect: AtlasRect,
+    shininess: f32,
+    // Only used by `fs_pbr`
+    metallic: f32,
+    roughness: f32,
+    ao: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: MaterialUniform;
+// Tangent space normals, materials without one get a flat (0, 0, 1)
+@group(0) @binding(5)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(6)
+var s_normal: sampler;
+// How much specular highlight there is (red channel), white when the material has no map
+@group(0) @binding(7)
+var t_specular: texture_2d<f32>;
+@group(0) @binding(8)
+var s_specular: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// How much of the light that rendered `shadow_map` reaches `world_position`, from 0 (none)
+// to 1 (all of it). Averages a 3x3 block of shadow map texels (PCF) so the shadow edges
+// aren't blocky.
+fn shadow_visibility(
+    shadow_map: texture_depth_2d,
+    light_view_proj: mat4x4<f32>,
+    world_position: vec3<f32>,
+) -> f32 {
+    let light_position = light_view_proj * vec4<f32>(world_position, 1.0);
+    // Behind a spot light
+    if (light_position.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = light_position.xyz / light_position.w;
+    // Clip space Y points up, texture V points down
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    // Outside what the light can see, so nothing there could be in the way
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel_size;
+            visibility = visibility + textureSampleCompareLevel(shadow_map, s_shadow, uv + offset, ndc.z);
+        }
+    }
+    return visibility / 9.0;
+}
+
+fn base_color(in: VertexOutput) -> vec4<f32> {
     // We use the special function `textureSample` to combine the texture data with coords
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    // Clamp like the sampler would, otherwise we'd read the neighbouring images in an atlas
+    let diffuse_uv = clamp(in.tex_coords, vec2<f32>(0.0), vec2<f32>(1.0)) * material.atlas_rect.size + material.atlas_rect.offset;
+    return textureSample(t_diffuse, s_diffuse, diffuse_uv) * in.color;
+}
 
-    let light_dir = normalize(light.position - in.world_position);
+// The vertex normal with the normal map applied
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+    // Turn the normal map from tangent space into world space. Interpolation can
+    // leave the tangent slightly off, so make it perpendicular to the normal again.
+    let vertex_normal = normalize(in.world_normal);
+    let tangent = normalize(in.world_tangent - vertex_normal * dot(vertex_normal, in.world_tangent));
+    let bitangent = cross(vertex_normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, vertex_normal);
+    let tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    return normalize(tbn * tangent_normal);
+}
+
+struct LightSample {
+    // Towards the light
+    direction: vec3<f32>,
+    // How much of the light gets here after the spot cone and shadows, from 0 to 1
+    visibility: f32,
+}
+
+fn sample_light(index: u32, world_position: vec3<f32>) -> LightSample {
+    let light = lights.lights[index];
+    var out: LightSample;
+
+    // Directional lights come from the same direction everywhere
+    out.direction = normalize(light.position - world_position);
+    if (light.light_type == 1u) {
+        out.direction = normalize(-light.direction);
+    }
+
+    // Spot lights fade out between the inner and outer cone
+    out.visibility = 1.0;
+    if (light.light_type == 2u) {
+        let theta = dot(-out.direction, light.direction);
+        out.visibility = smoothstep(light.outer_cutoff, light.inner_cutoff, theta);
+    }
+
+    if (index == shadow.light_index) {
+        out.visibility = out.visibility * shadow_visibility(t_shadow, shadow.light_view_proj, world_position);
+    }
+    if (index == spot_shadow.light_index) {
+        out.visibility = out.visibility * shadow_visibility(t_spot_shadow, spot_shadow.light_view_proj, world_position);
+    }
+    return out;
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color = base_color(in);
+    let lightmap_color: vec4<f32> = textureSample(t_lightmap, s_lightmap, in.tex_coords2);
+    let world_normal = surface_normal(in);
+    let specular_mask = textureSample(t_specular, s_specular, in.tex_coords).r;
+    
     let view_dir = normalize(camera.view_pos.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    // Add up what every light contributes
+    var light_color = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i = i + 1u) {
+        let light = lights.lights[i];
+
+        // We don't need (or want) much ambient light, so 0.1 is fine
+        let ambient_strength = 0.1;
+        let ambient_color = light.color * ambient_strength;
+
+        // Ambient light still gets into the shadows
+        let light_sample = sample_light(i, in.world_position);
+        let light_dir = light_sample.direction;
+        let half_dir = normalize(view_dir + light_dir);
 
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+        let diffuse_strength = max(dot(world_normal, light_dir), 0.0);
+        let diffuse_color = light.color * diffuse_strength * light_sample.visibility;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+        // Blinn-Phong
+        let specular_strength = pow(max(dot(world_normal, half_dir), 0.0), material.shininess) * specular_mask;
+        let specular_color = specular_strength * light.color * light_sample.visibility;
+
+        light_color = light_color + ambient_color + diffuse_color + specular_color;
+    }
+
+    let result = light_color * object_color.xyz * lightmap_color.xyz;
 
     return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
+
+// Cook-Torrance with a GGX distribution, Smith / Schlick-GGX geometry and Schlick's Fresnel.
+// Metals take their reflection color from the base color and have no diffuse.
+fn shade_pbr(in: VertexOutput) -> vec4<f32> {
+    let object_color = base_color(in);
+    let lightmap_color: vec4<f32> = textureSample(t_lightmap, s_lightmap, in.tex_coords2);
+    let albedo = object_color.rgb * lightmap_color.rgb;
+    let normal = surface_normal(in);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let pi = 3.14159265;
+
+    let metallic = material.metallic;
+    let roughness = material.roughness;
+    // Non-metals reflect about 4% head on
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let alpha = roughness * roughness;
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+
+    var light_color = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i = i + 1u) {
+        let light = lights.lights[i];
+        // Same ambient term as the Blinn-Phong shading, scaled by the occlusion
+        let ambient_color = light.color * 0.1 * albedo * material.ao;
+
+        let light_sample = sample_light(i, in.world_position);
+        let light_dir = light_sample.direction;
+        let half_dir = normalize(view_dir + light_dir);
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let h_dot_v = max(dot(half_dir, view_dir), 0.0);
+
+        let d_denominator = n_dot_h * n_dot_h * (alpha * alpha - 1.0) + 1.0;
+        let distribution = alpha * alpha / (pi * d_denominator * d_denominator);
+        let geometry = (n_dot_v / (n_dot_v * (1.0 - k) + k)) * (n_dot_l / (n_dot_l * (1.0 - k) + k));
+        let fresnel = f0 + (1.0 - f0) * pow(1.0 - h_dot_v, 5.0);
+
+        let specular = distribution * geometry * fresnel / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+        // Whatever isn't reflected gets diffused, unless it's a metal
+        let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * albedo / pi;
+
+        let radiance = light.color * light_sample.visibility;
+        light_color = light_color + ambient_color + (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    return vec4<f32>(light_color, object_color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
+
+@fragment
+fn fs_pbr(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade_pbr(in);
+}
+
+// Draws the triangle edges on top of the shaded surface. Works everywhere, unlike
+// `PolygonMode::Line`, but needs the mesh to have barycentric coordinates.
+fn wireframe(color: vec4<f32>, barycentric: vec3<f32>) -> vec4<f32> {
+    // Thickness in pixels
+    let wire_width = 1.5;
+    let wire_color = vec3<f32>(1.0, 1.0, 1.0);
+
+    // The barycentric coordinate goes to 0 on the opposite edge, fwidth keeps the wire the same width on screen
+    let edge = smoothstep(vec3<f32>(0.0), fwidth(barycentric) * wire_width, barycentric);
+    let wire = 1.0 - min(edge.x, min(edge.y, edge.z));
+    return vec4<f32>(mix(color.rgb, wire_color, wire), color.a);
+}
+
+@fragment
+fn fs_wireframe(in: VertexOutput) -> @location(0) vec4<f32> {
+    return wireframe(shade(in), in.barycentric);
+}
+
+@fragment
+fn fs_pbr_wireframe(in: VertexOutput) -> @location(0) vec4<f32> {
+    return wireframe(shade_pbr(in), in.barycentric);
+}
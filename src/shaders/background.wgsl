@@ -0,0 +1,30 @@
+// Fills the view with a vertical gradient, drawn before anything else in the main pass
+// Uses one oversized triangle instead of a quad, generated from the vertex index
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 at the top of the viewport, 1 at the bottom
+    @location(0) height: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in UV space covers the whole viewport
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.height = uv.y;
+    return out;
+}
+
+struct Gradient {
+    top_color: vec4<f32>,
+    bottom_color: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> gradient: Gradient;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return mix(gradient.top_color, gradient.bottom_color, in.height);
+}
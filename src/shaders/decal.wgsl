@@ -0,0 +1,49 @@
+// Projects a texture onto whatever is already in the depth buffer inside a box
+// The box is the unit cube (0 to 1 on every axis) transformed by the decal's model matrix
+
+struct View {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // x, y, width, height of the viewport in pixels
+    viewport: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var t_depth: texture_depth_2d;
+
+struct Decal {
+    model: mat4x4<f32>,
+    inv_model: mat4x4<f32>,
+}
+@group(2) @binding(0)
+var<uniform> decal: Decal;
+@group(2) @binding(1)
+var t_decal: texture_2d<f32>;
+@group(2) @binding(2)
+var s_decal: sampler;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return view.view_proj * decal.model * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_position: vec4<f32>) -> @location(0) vec4<f32> {
+    // Rebuild the world position of the surface behind this pixel from the depth buffer
+    let depth = textureLoad(t_depth, vec2<i32>(frag_position.xy), 0);
+    let screen_uv = (frag_position.xy - view.viewport.xy) / view.viewport.zw;
+    let ndc = vec4<f32>(screen_uv.x * 2.0 - 1.0, 1.0 - screen_uv.y * 2.0, depth, 1.0);
+    let world = view.inv_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+
+    // Only keep surfaces inside the decal's box
+    let local = (decal.inv_model * vec4<f32>(world_position, 1.0)).xyz;
+    if (any(local < vec3<f32>(0.0)) || any(local > vec3<f32>(1.0))) {
+        discard;
+    }
+
+    // Projected down the box's Z axis. Explicit LOD since we're after a discard.
+    return textureSampleLevel(t_decal, s_decal, vec2<f32>(local.x, 1.0 - local.y), 0.0);
+}
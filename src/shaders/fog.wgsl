@@ -0,0 +1,57 @@
+// Exponential squared fog, blended over the finished scene using the depth buffer
+// Drawn as one oversized triangle per viewport
+
+struct View {
+    inv_view_proj: mat4x4<f32>,
+    // x, y, width, height of the viewport in pixels
+    viewport: vec4<f32>,
+    camera_position: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var t_depth: texture_depth_2d;
+
+struct Fog {
+    color: vec4<f32>,
+    density: f32,
+    // World space height where the fog starts thinning out
+    height: f32,
+    // How quickly it thins out above `height`
+    height_falloff: f32,
+}
+@group(2) @binding(0)
+var<uniform> fog: Fog;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_position: vec4<f32>) -> @location(0) vec4<f32> {
+    let depth = textureLoad(t_depth, vec2<i32>(frag_position.xy), 0);
+    // Nothing was drawn here, leave the background alone
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    // Rebuild the world position of the surface behind this pixel
+    let screen_uv = (frag_position.xy - view.viewport.xy) / view.viewport.zw;
+    let ndc = vec4<f32>(screen_uv.x * 2.0 - 1.0, 1.0 - screen_uv.y * 2.0, depth, 1.0);
+    let world = view.inv_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+
+    // Linear distance from the camera, the raw depth value is non-linear
+    let distance = length(world_position - view.camera_position.xyz);
+    let density_distance = fog.density * distance;
+    var amount = 1.0 - exp(-density_distance * density_distance);
+
+    // Thinner the higher above the fog height the surface is
+    let above = max(world_position.y - fog.height, 0.0);
+    amount = amount * exp(-above * fog.height_falloff);
+
+    return vec4<f32>(fog.color.rgb, amount);
+}
@@ -0,0 +1,44 @@
+// Bitonic sort over a key (distance) buffer and a value (instance index) buffer
+// Each dispatch runs a single compare + swap step of the sorting network
+
+struct SortParams {
+    // Distance between the 2 elements we compare
+    j: u32,
+    // Size of the bitonic sequence we're currently merging
+    k: u32,
+    // Number of elements, the real ones plus padding up to a power of 2
+    padded_count: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> keys: array<f32>;
+@group(0) @binding(1)
+var<storage, read_write> values: array<u32>;
+@group(0) @binding(2)
+var<uniform> params: SortParams;
+
+@compute @workgroup_size(256)
+fn bitonic_step(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.padded_count) {
+        return;
+    }
+
+    // Only the lower element of each pair does the work
+    let partner = index ^ params.j;
+    if (partner <= index) {
+        return;
+    }
+
+    let ascending = (index & params.k) == 0u;
+    let key_a = keys[index];
+    let key_b = keys[partner];
+    if ((key_a > key_b) == ascending) {
+        keys[index] = key_b;
+        keys[partner] = key_a;
+        let value_a = values[index];
+        values[index] = values[partner];
+        values[partner] = value_a;
+    }
+}
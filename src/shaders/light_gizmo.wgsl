@@ -0,0 +1,87 @@
+// Draws a glowing dot where the light is, as a camera facing quad shaded to look like a sphere
+// The quad is generated from the vertex index, so no vertex buffers are needed
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    // 0 is a point light, 1 is directional, 2 is a spot light
+    light_type: u32,
+    color: vec3<f32>,
+    direction: vec3<f32>,
+    // Cosines of the spot light cone angles
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+}
+// Sized by `PhongConfig::max_lights`. On the web this becomes a fixed size uniform array,
+// see `pipeline::lights_shader_source`
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+@group(1) @binding(0)
+var<storage, read> lights: Lights;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 to 1 across the quad
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    // One quad per light
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let light = lights.lights[instance_index];
+    // 2 triangles: (-1,-1) (1,-1) (1,1), (-1,-1) (1,1) (-1,1)
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let to_camera = camera.view_pos.xyz - light.position;
+    let distance = length(to_camera);
+    let forward = to_camera / max(distance, 0.0001);
+    // Looking straight up or down would make the cross product zero, so pick another axis
+    var world_up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.99) {
+        world_up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(world_up, forward));
+    let up = cross(forward, right);
+
+    // Radius 0.1 at 10 units from the camera, scaled with the distance
+    // so it stays the same size on screen
+    let radius = 0.1 * distance / 10.0;
+    let world_position = light.position + (right * corner.x + up * corner.y) * radius;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.corner = corner;
+    out.color = light.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance_squared = dot(in.corner, in.corner);
+    if (distance_squared > 1.0) {
+        discard;
+    }
+    // Brightest in the middle, like light coming off a sphere. HDR, so it can go past 1.0
+    let glow = 1.0 + 2.0 * sqrt(1.0 - distance_squared);
+    return vec4<f32>(in.color * glow, 1.0);
+}
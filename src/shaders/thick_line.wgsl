@@ -0,0 +1,97 @@
+// Draws each line as a camera facing quad (2 triangles / 6 vertices)
+// The line data is pulled from a storage buffer using the vertex index
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Line {
+    start: vec3<f32>,
+    // Width in pixels
+    thickness: f32,
+    end: vec3<f32>,
+    color: vec4<f32>,
+}
+@group(1) @binding(0)
+var<storage, read> lines: array<Line>;
+
+struct Viewport {
+    size: vec2<f32>,
+}
+@group(1) @binding(1)
+var<uniform> viewport: Viewport;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // -1 to 1 across the width of the line
+    @location(1) across: f32,
+    // Distance in pixels from the start of the line (negative past the start cap)
+    @location(2) along: f32,
+    // Length of the line in pixels, and half the thickness
+    @location(3) length_radius: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let segment = lines[vertex_index / 6u];
+
+    // Map the 6 vertices to the quad corners: (0, 1, 2) and (2, 1, 3)
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index % 6u];
+
+    let clip_start = camera.view_proj * vec4<f32>(segment.start, 1.0);
+    let clip_end = camera.view_proj * vec4<f32>(segment.end, 1.0);
+
+    // Work out the line direction in pixels so the thickness is the same regardless of angle
+    let screen_start = clip_start.xy / clip_start.w * viewport.size * 0.5;
+    let screen_end = clip_end.xy / clip_end.w * viewport.size * 0.5;
+    let delta = screen_end - screen_start;
+    let line_length = length(delta);
+    var direction = vec2<f32>(1.0, 0.0);
+    if (line_length > 0.0) {
+        direction = delta / line_length;
+    }
+    let normal = vec2<f32>(-direction.y, direction.x);
+    let radius = segment.thickness * 0.5;
+
+    // Push the quad out sideways, and past each end by the radius so the tips can fade out
+    let extend = (corner.x * 2.0 - 1.0) * radius;
+    let offset_pixels = normal * corner.y * radius + direction * extend;
+
+    var clip = mix(clip_start, clip_end, corner.x);
+    clip = vec4<f32>(clip.xy + offset_pixels / (viewport.size * 0.5) * clip.w, clip.zw);
+
+    var out: VertexOutput;
+    out.clip_position = clip;
+    out.color = segment.color;
+    out.across = corner.y;
+    out.along = corner.x * line_length + extend;
+    out.length_radius = vec2<f32>(line_length, radius);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let line_length = in.length_radius.x;
+    let radius = max(in.length_radius.y, 0.5);
+
+    // Feather the last pixel on each side of the line
+    let edge_alpha = 1.0 - smoothstep(1.0 - 1.0 / radius, 1.0, abs(in.across));
+
+    // Fade out the caps past either end of the line
+    let tip_distance = max(-in.along, in.along - line_length);
+    let tip_alpha = 1.0 - smoothstep(0.0, radius, tip_distance);
+
+    return vec4<f32>(in.color.rgb, in.color.a * edge_alpha * tip_alpha);
+}
@@ -0,0 +1,103 @@
+// Maps the HDR scene down to the 0-1 range of the screen, and stretches it over
+// the whole target (the scene might be rendered at a lower resolution)
+// Uses one oversized triangle instead of a quad, generated from the vertex index
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2) in UV space covers the whole screen
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// `curve` matches `ToneMapOperator` on the Rust side
+struct ToneMap {
+    curve: u32,
+    exposure: f32,
+    // Only used by Reinhard extended - the brightness that maps to pure white
+    max_white: f32,
+    // 1 when the target isn't an sRGB format, so the gamma has to be applied here
+    encode_gamma: u32,
+}
+@group(0) @binding(2)
+var<uniform> tonemap: ToneMap;
+
+// John Hable's filmic curve from Uncharted 2
+fn uncharted2_partial(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn uncharted2(color: vec3<f32>) -> vec3<f32> {
+    let exposure_bias = 2.0;
+    let white = 11.2;
+    let curr = uncharted2_partial(color * exposure_bias);
+    let white_scale = vec3<f32>(1.0) / uncharted2_partial(vec3<f32>(white));
+    return curr * white_scale;
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_scene, s_scene, in.tex_coords);
+    let color = hdr.rgb * tonemap.exposure;
+
+    var mapped: vec3<f32>;
+    switch tonemap.curve {
+        // Reinhard
+        case 1u: {
+            mapped = color / (color + vec3<f32>(1.0));
+        }
+        // Reinhard extended
+        case 2u: {
+            let white_squared = tonemap.max_white * tonemap.max_white;
+            mapped = color * (vec3<f32>(1.0) + color / white_squared) / (color + vec3<f32>(1.0));
+        }
+        // ACES
+        case 3u: {
+            mapped = aces(color);
+        }
+        // Uncharted 2
+        case 4u: {
+            mapped = uncharted2(color);
+        }
+        // Linear - just clip anything over 1.0
+        default: {
+            mapped = color;
+        }
+    }
+
+    mapped = clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+    // sRGB targets gamma encode on write, anything else needs it done by hand
+    if (tonemap.encode_gamma == 1u) {
+        mapped = pow(mapped, vec3<f32>(1.0 / 2.2));
+    }
+
+    return vec4<f32>(mapped, hdr.a);
+}
@@ -0,0 +1,56 @@
+// Gets transparent instances ready to draw back to front: `distances` writes the keys and
+// values for the GPU sort, then `gather` copies the instances (and their colors) into the
+// order the sort left the values in
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Same layout as `InstanceRaw`, a 4x4 model matrix followed by a 3x3 normal matrix.
+// A plain array since a mat3x3 in a storage buffer would be padded out to 4 floats per column.
+struct Instance {
+    data: array<f32, 25>,
+}
+@group(0) @binding(1)
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(2)
+var<storage, read> colors: array<vec4<f32>>;
+@group(0) @binding(3)
+var<storage, read_write> keys: array<f32>;
+@group(0) @binding(4)
+var<storage, read_write> values: array<u32>;
+@group(0) @binding(5)
+var<storage, read_write> sorted_instances: array<Instance>;
+@group(0) @binding(6)
+var<storage, read_write> sorted_colors: array<vec4<f32>>;
+
+@compute @workgroup_size(256)
+fn distances(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&instances)) {
+        return;
+    }
+    // The translation is the last column of the model matrix
+    let position = vec3<f32>(
+        instances[index].data[12],
+        instances[index].data[13],
+        instances[index].data[14],
+    );
+    // Negative so the ascending sort puts the farthest first
+    keys[index] = -distance(position, camera.view_pos.xyz);
+    values[index] = index;
+}
+
+@compute @workgroup_size(256)
+fn gather(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&instances)) {
+        return;
+    }
+    let source = values[index];
+    sorted_instances[index] = instances[source];
+    sorted_colors[index] = colors[source];
+}
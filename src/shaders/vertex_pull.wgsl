@@ -0,0 +1,76 @@
+// Same output as `vs_main` in shader.wgsl, but the vertex data is read straight from a
+// storage buffer using the vertex index instead of going through a vertex buffer layout
+// Paired with the fragment shader from shader.wgsl
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// `ModelVertex` data as plain floats - a struct with vec3s would get padded to 16 bytes
+@group(3) @binding(0)
+var<storage, read> vertices: array<f32>;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) tex_coords2: vec2<f32>,
+    @location(4) barycentric: vec3<f32>,
+    @location(5) color: vec4<f32>,
+    @location(6) world_tangent: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    // With indexed draws this is the value from the index buffer
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // position (3) + tex_coords (2) + normal (3) + tex_coords2 (2) + barycentric (3) + tangent (3)
+    let base = vertex_index * 16u;
+    let position = vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+    let tex_coords = vec2<f32>(vertices[base + 3u], vertices[base + 4u]);
+    let normal = vec3<f32>(vertices[base + 5u], vertices[base + 6u], vertices[base + 7u]);
+    let tex_coords2 = vec2<f32>(vertices[base + 8u], vertices[base + 9u]);
+    let barycentric = vec3<f32>(vertices[base + 10u], vertices[base + 11u], vertices[base + 12u]);
+    let tangent = vec3<f32>(vertices[base + 13u], vertices[base + 14u], vertices[base + 15u]);
+
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    var out: VertexOutput;
+    out.tex_coords = tex_coords;
+    out.tex_coords2 = tex_coords2;
+    out.barycentric = barycentric;
+    out.color = instance.color;
+    out.world_normal = normal_matrix * normal;
+    out.world_tangent = normal_matrix * tangent;
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    return out;
+}
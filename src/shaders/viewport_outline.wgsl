@@ -0,0 +1,21 @@
+// Draws a 1 pixel border around whatever viewport is currently set
+// The corners are generated from the vertex index, so no vertex buffer is needed
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // Pulled in slightly so the lines don't get clipped at the very edge
+    let inset = 0.998;
+    var corners = array<vec2<f32>, 5>(
+        vec2<f32>(-inset, -inset),
+        vec2<f32>(inset, -inset),
+        vec2<f32>(inset, inset),
+        vec2<f32>(-inset, inset),
+        vec2<f32>(-inset, -inset),
+    );
+    return vec4<f32>(corners[vertex_index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.8, 0.2, 1.0);
+}
@@ -0,0 +1,223 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::instance::{Instance, InstanceColor};
+use crate::model::{self, ModelVertex};
+use crate::primitives::{self, Axis, WindingOrder};
+use crate::{resources, texture};
+
+// Where the row of primitives starts, behind the instanced models
+const ROW_START: [f32; 3] = [-9.0, 1.0, 22.0];
+// Gap between the primitives along X
+const ROW_SPACING: f32 = 3.0;
+// Packed into one atlas, the primitives take turns using them
+const TEXTURES: [&str; 2] = ["cube-diffuse.jpg", "banana.png"];
+const ATLAS_SIZE: u32 = 1024;
+
+// Vertices and indices, as the generators return them
+type Geometry = (Vec<ModelVertex>, Vec<u32>);
+
+// Swaps the generated UVs for one of the projections in `primitives`
+fn reproject(
+    (mut vertices, mut indices): Geometry,
+    project: impl FnOnce(&mut Vec<ModelVertex>, &mut Vec<u32>),
+) -> Geometry {
+    project(&mut vertices, &mut indices);
+    // Tangents follow the UVs, so they have to be redone too
+    model::compute_tangents(&mut vertices, &indices);
+    (vertices, indices)
+}
+
+// Points the normals of a mesh generated with `WindingOrder::Clockwise` inwards. Together with
+// the flipped winding its inside becomes the front, so it's culled and lit like a dome or a room.
+fn inside_out((mut vertices, indices): Geometry) -> Geometry {
+    for vertex in vertices.iter_mut() {
+        vertex.normal = vertex.normal.map(|n| -n);
+    }
+    (vertices, indices)
+}
+
+// A row with one of each generated primitive, plus an axis gizmo at the end of it,
+// so the generators can be checked by eye next to the loaded model.
+// The materials share a texture atlas, and every primitive gets tinted with an instance color.
+pub struct Showcase {
+    pub model: model::Model,
+    // Instances for each mesh in `model.meshes`, in the same order
+    pub instances: Vec<Range<u32>>,
+    pub instance_buffer: wgpu::Buffer,
+    pub instance_color_buffer: wgpu::Buffer,
+}
+
+impl Showcase {
+    // `wireframe` unwelds the meshes so they work with the barycentric wireframe shader
+    pub async fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        wireframe: bool,
+    ) -> anyhow::Result<Self> {
+        let winding = WindingOrder::default();
+        let shapes: [(&str, Geometry, [f32; 4]); 13] = [
+            (
+                "Sphere",
+                primitives::generate_sphere(1.0, 32, 16, winding),
+                [0.9, 0.3, 0.3, 1.0],
+            ),
+            (
+                "Icosphere",
+                primitives::icosphere::generate_icosphere(1.0, 2, winding),
+                [0.9, 0.6, 0.2, 1.0],
+            ),
+            (
+                "Torus",
+                primitives::torus::generate_torus(0.8, 0.3, 32, 16, winding),
+                [0.9, 0.9, 0.3, 1.0],
+            ),
+            (
+                "Cylinder",
+                primitives::cylinder::generate_cylinder(0.8, 2.0, 32, winding),
+                [0.3, 0.9, 0.4, 1.0],
+            ),
+            (
+                "Cone",
+                primitives::cone::generate_cone(0.9, 2.0, 32, winding),
+                [0.3, 0.8, 0.9, 1.0],
+            ),
+            (
+                "Capsule",
+                primitives::capsule::generate_capsule(0.6, 1.0, 32, 16, winding),
+                [0.4, 0.4, 0.9, 1.0],
+            ),
+            (
+                "Grid",
+                primitives::plane::generate_grid(2.0, 4, 4, true, winding),
+                [0.8, 0.4, 0.9, 1.0],
+            ),
+            // The same shapes again with projected UVs, to compare against the generated ones
+            (
+                "Torus (Planar UVs)",
+                reproject(
+                    primitives::torus::generate_torus(0.8, 0.3, 32, 16, winding),
+                    |vertices, _| primitives::uv_project_planar(vertices, Axis::Y, 0.5),
+                ),
+                [0.9, 0.9, 0.3, 1.0],
+            ),
+            // Looking at it from the side, so the texture stretches along the slope
+            (
+                "Cone (Planar UVs)",
+                reproject(
+                    primitives::cone::generate_cone(0.9, 2.0, 32, winding),
+                    |vertices, _| primitives::uv_project_planar(vertices, Axis::X, 0.5),
+                ),
+                [0.3, 0.8, 0.9, 1.0],
+            ),
+            (
+                "Icosphere (Planar UVs)",
+                reproject(
+                    primitives::icosphere::generate_icosphere(1.0, 2, winding),
+                    |vertices, _| primitives::uv_project_planar(vertices, Axis::Z, 0.5),
+                ),
+                [0.9, 0.6, 0.2, 1.0],
+            ),
+            (
+                "Cylinder (Cylindrical UVs)",
+                reproject(
+                    primitives::cylinder::generate_cylinder(0.8, 2.0, 32, winding),
+                    |vertices, indices| {
+                        primitives::uv_project_cylindrical(vertices, 0.5);
+                        primitives::uv_fix_seams(vertices, indices);
+                    },
+                ),
+                [0.3, 0.9, 0.4, 1.0],
+            ),
+            (
+                "Capsule (Spherical UVs)",
+                reproject(
+                    primitives::capsule::generate_capsule(0.6, 1.0, 32, 16, winding),
+                    |vertices, indices| {
+                        primitives::uv_project_spherical(vertices);
+                        primitives::uv_fix_seams(vertices, indices);
+                    },
+                ),
+                [0.4, 0.4, 0.9, 1.0],
+            ),
+            // Only the far side shows from out here, like looking into a bowl
+            (
+                "Inside-Out Sphere",
+                inside_out(primitives::generate_sphere(
+                    1.0,
+                    32,
+                    16,
+                    WindingOrder::Clockwise,
+                )),
+                [0.9, 0.3, 0.3, 1.0],
+            ),
+        ];
+
+        let mut meshes = Vec::with_capacity(shapes.len() + 1);
+        let mut instances = Vec::with_capacity(shapes.len() + 1);
+        let mut instance_data = Vec::new();
+        let mut color_data = Vec::new();
+        let mut push_mesh =
+            |name: &str,
+             (mut vertices, mut indices): Geometry,
+             mesh_instances: &[(Instance, InstanceColor)]| {
+                if wireframe {
+                    primitives::add_barycentric_coordinates(&mut vertices, &mut indices);
+                }
+                let material = meshes.len() % TEXTURES.len();
+                meshes.push(model::Mesh::new(device, name, vertices, indices, material));
+                let start = instance_data.len() as u32;
+                instances.push(start..start + mesh_instances.len() as u32);
+                for (instance, color) in mesh_instances {
+                    instance_data.push(instance.to_raw());
+                    color_data.push(*color);
+                }
+            };
+
+        let mut position = cgmath::Vector3::from(ROW_START);
+        for (name, shape, color) in shapes {
+            let instance = Instance {
+                position,
+                rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            };
+            push_mesh(name, shape, &[(instance, InstanceColor { color })]);
+            position.x += ROW_SPACING;
+        }
+        // One arrow drawn 3 times, the gizmo sits on the ground so it starts lower than the rest
+        position.y -= 1.0;
+        push_mesh(
+            "Axis Gizmo",
+            primitives::arrow::generate_arrow(0.08, 1.4, 0.2, 0.6, 16, winding),
+            &primitives::arrow::axis_gizmo_instances(position),
+        );
+
+        let mut atlas = texture::TextureAtlas::new(ATLAS_SIZE);
+        let materials =
+            resources::load_atlas_materials(&TEXTURES, &mut atlas, device, queue, layout).await?;
+
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Showcase Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let instance_color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Showcase Instance Color Buffer"),
+            contents: bytemuck::cast_slice(&color_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Ok(Self {
+            model: model::Model { meshes, materials },
+            instances,
+            instance_buffer,
+            instance_color_buffer,
+        })
+    }
+
+    // Meshes paired with the instances they get drawn with
+    pub fn draws(&self) -> impl Iterator<Item = (&model::Mesh, Range<u32>)> {
+        self.model.meshes.iter().zip(self.instances.iter().cloned())
+    }
+}
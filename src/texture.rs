@@ -7,15 +7,74 @@ pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub size: wgpu::Extent3d,
+    // Color textures are sRGB, data textures (normals, roughness, etc) need to stay linear
+    pub format: wgpu::TextureFormat,
+}
+
+// Depth buffer formats we render with. The stencil ones are only needed for stencil effects.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DepthFormat {
+    #[default]
+    Depth32Float,
+    Depth24PlusStencil8,
+    // Needs `Features::DEPTH32FLOAT_STENCIL8`
+    Depth32FloatStencil8,
+}
+
+impl DepthFormat {
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            DepthFormat::Depth32Float => wgpu::TextureFormat::Depth32Float,
+            DepthFormat::Depth24PlusStencil8 => wgpu::TextureFormat::Depth24PlusStencil8,
+            DepthFormat::Depth32FloatStencil8 => wgpu::TextureFormat::Depth32FloatStencil8,
+        }
+    }
+
+    // Features the device has to be created with to use this format
+    pub fn required_features(&self) -> wgpu::Features {
+        match self {
+            DepthFormat::Depth32FloatStencil8 => wgpu::Features::DEPTH32FLOAT_STENCIL8,
+            _ => wgpu::Features::empty(),
+        }
+    }
+
+    // Falls back to `Depth24PlusStencil8` (which every device has) if the adapter is missing a feature
+    pub fn supported_or_fallback(self, adapter: &wgpu::Adapter) -> Self {
+        if adapter.features().contains(self.required_features()) {
+            self
+        } else {
+            log::warn!(
+                "{:?} isn't supported, using Depth24PlusStencil8 instead",
+                self
+            );
+            DepthFormat::Depth24PlusStencil8
+        }
+    }
+
+    // Cycles through every format, for comparing them at runtime
+    pub fn next(&self) -> Self {
+        match self {
+            DepthFormat::Depth32Float => DepthFormat::Depth24PlusStencil8,
+            DepthFormat::Depth24PlusStencil8 => DepthFormat::Depth32FloatStencil8,
+            DepthFormat::Depth32FloatStencil8 => DepthFormat::Depth32Float,
+        }
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        !matches!(self, DepthFormat::Depth32Float)
+    }
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    // The scene is rendered in floating point so lighting can go past 1.0 before tone mapping
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
     // Create a new texture to contain the depth information of scene
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        depth_format: DepthFormat,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -29,7 +88,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format: depth_format.format(),
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
         };
         let texture = device.create_texture(&desc);
@@ -51,6 +110,51 @@ impl Texture {
             texture,
             view,
             sampler,
+            size,
+            format: depth_format.format(),
+        }
+    }
+
+    // A texture we can draw into and then sample from in a later pass
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Linear filtering so it can be scaled up smoothly
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+            format,
         }
     }
 
@@ -65,12 +169,47 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    // Same as `from_bytes`, but the data is used as-is instead of being gamma decoded.
+    // Use this for anything that isn't a color (normal maps, roughness, etc).
+    pub fn from_bytes_linear(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+    }
+
     // Generate texture from image data
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(
+            device,
+            queue,
+            img,
+            label,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    // `format` should be `Rgba8UnormSrgb` or `Rgba8Unorm`, the pixels are always uploaded as RGBA8
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -86,25 +225,11 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: NonZeroU32::new(dimensions.1),
-            },
-            size,
-        );
+        Self::write_rgba(queue, &texture, &rgba, size);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -121,6 +246,268 @@ impl Texture {
             texture,
             view,
             sampler,
+            size,
+            format,
         })
     }
+
+    // A 1x1 texture filled with one color, handy as a default when a material is missing a map
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        // Can't fail, `from_image` only errors when decoding
+        Self::from_image(device, queue, &img, Some(label)).unwrap()
+    }
+
+    // Same as `from_color`, but for data like normals that shouldn't be gamma decoded
+    pub fn from_color_linear(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        // Can't fail, `from_image_with_format` only errors when decoding
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+        .unwrap()
+    }
+
+    // An 8x8 checker pattern generated in memory, `size` pixels across. Easier to spot
+    // than a solid color when a material is missing its texture.
+    pub fn create_checkerboard(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        color_a: [u8; 4],
+        color_b: [u8; 4],
+        label: &str,
+    ) -> Self {
+        let size = size.max(8);
+        let cell = size / 8;
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(size, size, |x, y| {
+            if ((x / cell) ^ (y / cell)) & 1 == 0 {
+                image::Rgba(color_a)
+            } else {
+                image::Rgba(color_b)
+            }
+        }));
+        // Can't fail, `from_image` only errors when decoding
+        Self::from_image(device, queue, &img, Some(label)).unwrap()
+    }
+
+    // Re-read an image from disk into this texture (e.g. when an artist saves over it).
+    // If the size is the same we just upload the new pixels, otherwise we need a brand new
+    // texture - returns `true` in that case so any bind groups using the old view get recreated.
+    pub fn reload_from_path(
+        &mut self,
+        path: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<bool> {
+        let bytes = std::fs::read(path)?;
+        let img = image::load_from_memory(&bytes)?;
+        let dimensions = img.dimensions();
+
+        if dimensions != (self.size.width, self.size.height) {
+            // Keep the color space we were originally loaded with
+            *self = Self::from_image_with_format(device, queue, &img, Some(path), self.format)?;
+            return Ok(true);
+        }
+
+        Self::write_rgba(queue, &self.texture, &img.to_rgba8(), self.size);
+        Ok(false)
+    }
+
+    // Copy RGBA pixel data into the whole texture
+    fn write_rgba(queue: &wgpu::Queue, texture: &wgpu::Texture, rgba: &[u8], size: wgpu::Extent3d) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * size.width),
+                rows_per_image: NonZeroU32::new(size.height),
+            },
+            size,
+        );
+    }
+}
+
+// Where an image ended up inside a `TextureAtlas`, in UV space (0-1).
+// Laid out to match the `AtlasRect` in `shader.wgsl`'s material uniform.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AtlasRect {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+
+impl AtlasRect {
+    // Covers the whole texture, so UVs pass through unchanged
+    pub const FULL: Self = Self {
+        offset: [0.0, 0.0],
+        size: [1.0, 1.0],
+    };
+}
+
+// A row of images that all fit under the tallest one
+struct Shelf {
+    y: u32,
+    height: u32,
+    // Where the next image on this shelf goes
+    x: u32,
+}
+
+// Packs lots of small images into one big texture, so objects can share a single bind group.
+// Images are placed on shelves (rows), each new image going on the shelf that wastes the least height.
+pub struct TextureAtlas {
+    max_size: u32,
+    image: image::RgbaImage,
+    shelves: Vec<Shelf>,
+    // Pixel rects (x, y, width, height) added since the last `upload`
+    pending: Vec<(u32, u32, u32, u32)>,
+    texture: Option<Texture>,
+}
+
+impl TextureAtlas {
+    // Space between images so linear filtering doesn't bleed neighbours in
+    const PADDING: u32 = 1;
+
+    // The atlas is always `max_size` x `max_size` pixels
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            image: image::RgbaImage::new(max_size, max_size),
+            shelves: Vec::new(),
+            pending: Vec::new(),
+            texture: None,
+        }
+    }
+
+    // Decode an image and copy it into the atlas. It shows up on the GPU after the next `upload`.
+    pub fn add(&mut self, image_bytes: &[u8], label: &str) -> Result<AtlasRect> {
+        let img = image::load_from_memory(image_bytes)?.to_rgba8();
+        let (width, height) = img.dimensions();
+        let (x, y) = self
+            .allocate(width + Self::PADDING, height + Self::PADDING)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} ({}x{}) doesn't fit in the {}x{} texture atlas",
+                    label,
+                    width,
+                    height,
+                    self.max_size,
+                    self.max_size
+                )
+            })?;
+
+        image::imageops::replace(&mut self.image, &img, x as i64, y as i64);
+        self.pending.push((x, y, width, height));
+
+        let max_size = self.max_size as f32;
+        Ok(AtlasRect {
+            offset: [x as f32 / max_size, y as f32 / max_size],
+            size: [width as f32 / max_size, height as f32 / max_size],
+        })
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let max_size = self.max_size;
+        let best_shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf.x + width <= max_size)
+            .min_by_key(|shelf| shelf.height - height);
+        if let Some(shelf) = best_shelf {
+            let x = shelf.x;
+            shelf.x += width;
+            return Some((x, shelf.y));
+        }
+
+        // Nothing fits, start a new shelf below the last one
+        let y = self
+            .shelves
+            .last()
+            .map(|shelf| shelf.y + shelf.height)
+            .unwrap_or(0);
+        if width > max_size || y + height > max_size {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            x: width,
+        });
+        Some((0, y))
+    }
+
+    // Copy anything added since the last call to the GPU. The first call creates the texture,
+    // after that only the new regions get written.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> &Texture {
+        let texture = match self.texture.take() {
+            Some(texture) => {
+                for &(x, y, width, height) in &self.pending {
+                    let region = image::imageops::crop_imm(&self.image, x, y, width, height);
+                    Self::write_region(queue, &texture.texture, &region.to_image(), x, y);
+                }
+                texture
+            }
+            // Can't fail, `from_image` only errors when decoding
+            None => Texture::from_image(
+                device,
+                queue,
+                &image::DynamicImage::ImageRgba8(self.image.clone()),
+                Some("Texture Atlas"),
+            )
+            .unwrap(),
+        };
+        self.pending.clear();
+        self.texture.insert(texture)
+    }
+
+    fn write_region(
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        rgba: &image::RgbaImage,
+        x: u32,
+        y: u32,
+    ) {
+        let (width, height) = rgba.dimensions();
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 }
@@ -0,0 +1,127 @@
+// How the window gets split up into views
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ViewportLayout {
+    Single,
+    // Perspective, top, front and side views in the 4 corners (like most 3D editors)
+    FourWay,
+    // Perspective on the left 70%, an orthographic top down view of the scene on the right
+    Overview,
+}
+
+// A region of the window in physical pixels, with the origin at the top left
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ViewportRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ViewportRect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64
+            && y >= self.y as f64
+            && x < (self.x + self.width) as f64
+            && y < (self.y + self.height) as f64
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    // wgpu doesn't allow empty viewports, which we get if the window is shrunk to a sliver
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+pub struct ViewportConfig {
+    pub layout: ViewportLayout,
+    // The view that receives input (and gets the outline)
+    pub active: usize,
+}
+
+impl ViewportConfig {
+    pub fn new(layout: ViewportLayout) -> Self {
+        Self { layout, active: 0 }
+    }
+
+    pub fn toggle_layout(&mut self) {
+        self.layout = match self.layout {
+            ViewportLayout::Single => ViewportLayout::FourWay,
+            ViewportLayout::FourWay => ViewportLayout::Overview,
+            ViewportLayout::Overview => ViewportLayout::Single,
+        };
+        self.active = 0;
+    }
+
+    // The rects for each view, in order: perspective (top left), top (top right),
+    // front (bottom left) and side (bottom right). The overview layout is perspective, then overview.
+    pub fn rects(&self, width: u32, height: u32) -> Vec<ViewportRect> {
+        match self.layout {
+            ViewportLayout::Single => vec![ViewportRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+            ViewportLayout::FourWay => {
+                // The right / bottom halves get the leftover pixel on odd sizes
+                let left = width / 2;
+                let top = height / 2;
+                let right = width - left;
+                let bottom = height - top;
+                vec![
+                    ViewportRect {
+                        x: 0,
+                        y: 0,
+                        width: left,
+                        height: top,
+                    },
+                    ViewportRect {
+                        x: left,
+                        y: 0,
+                        width: right,
+                        height: top,
+                    },
+                    ViewportRect {
+                        x: 0,
+                        y: top,
+                        width: left,
+                        height: bottom,
+                    },
+                    ViewportRect {
+                        x: left,
+                        y: top,
+                        width: right,
+                        height: bottom,
+                    },
+                ]
+            }
+            ViewportLayout::Overview => {
+                let left = width * 7 / 10;
+                vec![
+                    ViewportRect {
+                        x: 0,
+                        y: 0,
+                        width: left,
+                        height,
+                    },
+                    ViewportRect {
+                        x: left,
+                        y: 0,
+                        width: width - left,
+                        height,
+                    },
+                ]
+            }
+        }
+    }
+
+    // Which view is under the cursor
+    pub fn viewport_at(&self, width: u32, height: u32, x: f64, y: f64) -> Option<usize> {
+        self.rects(width, height)
+            .iter()
+            .position(|rect| rect.contains(x, y))
+    }
+}